@@ -6,11 +6,22 @@ use std::env;
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=res/*");
 
+    // Gamepads go through gilrs wherever Cargo.toml pulls it in: always on
+    // Windows, macOS and the web, and with the `gamepad` feature elsewhere.
+    println!("cargo:rustc-check-cfg=cfg(gilrs)");
+    let target_os = env::var("CARGO_CFG_TARGET_OS")?;
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH")?;
+    if env::var_os("CARGO_FEATURE_GAMEPAD").is_some()
+        || matches!(target_os.as_str(), "windows" | "macos")
+        || target_arch == "wasm32"
+    {
+        println!("cargo:rustc-cfg=gilrs");
+    }
+
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("res/");
+    let paths_to_copy = vec!["res/"];
     copy_items(&paths_to_copy, out_dir, &copy_options)?;
 
     Ok(())
@@ -0,0 +1,413 @@
+use cgmath::{InnerSpace, SquareMatrix, VectorSpace};
+use wgpu::util::DeviceExt;
+
+use crate::instance::{Instance, InstanceBuffer};
+use crate::model::SkinnedModel;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Transform {
+    pub fn to_matrix(self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Blends towards `other`, `factor` of the way from 0 to 1.
+    pub fn lerp(self, other: Transform, factor: f32) -> Transform {
+        let mut to = other.rotation;
+        // Take the short way round.
+        if self.rotation.dot(to) < 0.0 {
+            to = -to;
+        }
+        Transform {
+            translation: self.translation.lerp(other.translation, factor),
+            rotation: self.rotation.slerp(to, factor).normalize(),
+            scale: self.scale.lerp(other.scale, factor),
+        }
+    }
+}
+
+pub struct SkeletonNode {
+    pub parent: Option<usize>,
+    pub rest: Transform,
+}
+
+/// The node hierarchy of a glTF file together with the skin that binds mesh
+/// vertices to some of its nodes.
+pub struct Skeleton {
+    pub nodes: Vec<SkeletonNode>,
+    /// Node indices ordered so that every parent comes before its children.
+    pub order: Vec<usize>,
+    /// Node index of each joint referenced by `SkinnedVertex::joints`.
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<cgmath::Matrix4<f32>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+}
+
+pub enum Keyframes {
+    Translation(Vec<cgmath::Vector3<f32>>),
+    Rotation(Vec<cgmath::Quaternion<f32>>),
+    Scale(Vec<cgmath::Vector3<f32>>),
+}
+
+pub struct Channel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+pub struct AnimationClip {
+    #[allow(unused)]
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl Channel {
+    /// Returns the keyframes surrounding `time` and how far between them it
+    /// lies. Times outside the channel clamp to its first or last keyframe.
+    fn locate(&self, time: f32) -> (usize, usize, f32) {
+        let last = self.times.len() - 1;
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next > last {
+            return (last, last, 0.0);
+        }
+        let prev = next - 1;
+        let factor = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => {
+                (time - self.times[prev]) / (self.times[next] - self.times[prev])
+            }
+        };
+        (prev, next, factor)
+    }
+
+    fn apply(&self, time: f32, transform: &mut Transform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (prev, next, factor) = self.locate(time);
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                transform.translation = values[prev].lerp(values[next], factor);
+            }
+            Keyframes::Rotation(values) => {
+                let from = values[prev];
+                let mut to = values[next];
+                // Take the short way round.
+                if from.dot(to) < 0.0 {
+                    to = -to;
+                }
+                transform.rotation = from.slerp(to, factor).normalize();
+            }
+            Keyframes::Scale(values) => {
+                transform.scale = values[prev].lerp(values[next], factor);
+            }
+        }
+    }
+}
+
+impl AnimationClip {
+    /// Poses every node of `skeleton` at `time` seconds into the clip.
+    pub fn sample(&self, skeleton: &Skeleton, time: f32) -> Vec<Transform> {
+        let mut pose = skeleton.nodes.iter().map(|n| n.rest).collect::<Vec<_>>();
+        for channel in &self.channels {
+            channel.apply(time, &mut pose[channel.node]);
+        }
+        pose
+    }
+}
+
+impl Skeleton {
+    /// Skinning matrices for each joint, taking bind-pose vertices to the
+    /// posed model space.
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<cgmath::Matrix4<f32>> {
+        let mut global = vec![cgmath::Matrix4::identity(); self.nodes.len()];
+        for &node in &self.order {
+            let local = pose[node].to_matrix();
+            global[node] = match self.nodes[node].parent {
+                Some(parent) => global[parent] * local,
+                None => local,
+            };
+        }
+
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(&node, inverse_bind)| global[node] * inverse_bind)
+            .collect()
+    }
+}
+
+pub struct Animator {
+    pub clip: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl Animator {
+    pub fn new(clip: usize) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    pub fn advance(&mut self, delta: f32, clips: &[AnimationClip]) {
+        let Some(clip) = clips.get(self.clip) else {
+            return;
+        };
+        self.time += delta * self.speed;
+        if self.looping && clip.duration > 0.0 {
+            self.time = self.time.rem_euclid(clip.duration);
+        } else {
+            self.time = self.time.clamp(0.0, clip.duration);
+        }
+    }
+
+    pub fn joint_matrices(&self, model: &SkinnedModel) -> Vec<cgmath::Matrix4<f32>> {
+        let pose = match model.clips.get(self.clip) {
+            Some(clip) => clip.sample(&model.skeleton, self.time),
+            None => model.skeleton.nodes.iter().map(|n| n.rest).collect(),
+        };
+        model.skeleton.joint_matrices(&pose)
+    }
+}
+
+/// A skinned model placed in the world, with the GPU buffers its pose and
+/// instances live in.
+pub struct AnimatedModel {
+    pub model: SkinnedModel,
+    pub animator: Animator,
+    pub instances: InstanceBuffer,
+    joint_buffer: wgpu::Buffer,
+    pub joint_bind_group: wgpu::BindGroup,
+}
+
+impl AnimatedModel {
+    pub fn new(
+        device: &wgpu::Device,
+        model: SkinnedModel,
+        instances: Vec<Instance>,
+        joint_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let animator = Animator::new(0);
+
+        let joint_data = Self::joint_data(&animator, &model);
+        let joint_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Joint Buffer"),
+            contents: bytemuck::cast_slice(&joint_data),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let joint_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: joint_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: joint_buffer.as_entire_binding(),
+            }],
+            label: Some("joint_bind_group"),
+        });
+
+        let instances = InstanceBuffer::new(device, instances, "Animated Instance Buffer");
+
+        Self {
+            model,
+            animator,
+            instances,
+            joint_buffer,
+            joint_bind_group,
+        }
+    }
+
+    fn joint_data(animator: &Animator, model: &SkinnedModel) -> Vec<[[f32; 4]; 4]> {
+        let mut data = animator
+            .joint_matrices(model)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        // Storage bindings can't be empty.
+        if data.is_empty() {
+            data.push(cgmath::Matrix4::identity().into());
+        }
+        data
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        self.animator.advance(delta, &self.model.clips);
+    }
+
+    /// Uploads the current pose and any instance changes.
+    pub fn sync(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let joint_data = Self::joint_data(&self.animator, &self.model);
+        queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&joint_data));
+        self.instances.sync(device, queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Rotation3;
+
+    fn turned(degrees: f32) -> cgmath::Quaternion<f32> {
+        cgmath::Quaternion::from_angle_y(cgmath::Deg(degrees))
+    }
+
+    #[test]
+    fn transform_lerp_blends_each_part() {
+        let from = Transform {
+            translation: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            rotation: turned(0.0),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        };
+        let to = Transform {
+            translation: cgmath::Vector3::new(4.0, 0.0, -2.0),
+            rotation: turned(90.0),
+            scale: cgmath::Vector3::new(3.0, 1.0, 1.0),
+        };
+        let blended = from.lerp(to, 0.5);
+        assert_eq!(blended.translation, cgmath::Vector3::new(2.0, 0.0, -1.0));
+        assert_eq!(blended.scale, cgmath::Vector3::new(2.0, 1.0, 1.0));
+        assert!((blended.rotation - turned(45.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn transform_lerp_takes_the_short_way_round() {
+        let from = Transform {
+            translation: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            rotation: turned(10.0),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        };
+        // The same turn as 30 degrees, but on the far side of the sphere.
+        let to = Transform {
+            rotation: -turned(30.0),
+            ..from
+        };
+        let blended = from.lerp(to, 0.5);
+        assert!((blended.rotation - turned(20.0)).magnitude() < 1e-5);
+    }
+
+    fn rest() -> Transform {
+        Transform {
+            translation: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            rotation: turned(0.0),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Two unparented nodes, with nothing bound to them.
+    fn skeleton() -> Skeleton {
+        Skeleton {
+            nodes: (0..2)
+                .map(|_| SkeletonNode {
+                    parent: None,
+                    rest: rest(),
+                })
+                .collect(),
+            order: vec![0, 1],
+            joints: Vec::new(),
+            inverse_bind_matrices: Vec::new(),
+        }
+    }
+
+    /// A clip moving node 0 along X from 0 at 1s to 4 at 2s and 6 at 4s.
+    fn clip(interpolation: Interpolation) -> AnimationClip {
+        AnimationClip {
+            name: String::new(),
+            duration: 4.0,
+            channels: vec![Channel {
+                node: 0,
+                interpolation,
+                times: vec![1.0, 2.0, 4.0],
+                keyframes: Keyframes::Translation(
+                    [0.0, 4.0, 6.0]
+                        .map(|x| cgmath::Vector3::new(x, 0.0, 0.0))
+                        .to_vec(),
+                ),
+            }],
+        }
+    }
+
+    fn x_at(clip: &AnimationClip, time: f32) -> f32 {
+        clip.sample(&skeleton(), time)[0].translation.x
+    }
+
+    #[test]
+    fn linear_channels_blend_between_keyframes() {
+        let clip = clip(Interpolation::Linear);
+        assert_eq!(x_at(&clip, 1.0), 0.0);
+        assert_eq!(x_at(&clip, 1.25), 1.0);
+        assert_eq!(x_at(&clip, 2.0), 4.0);
+        assert_eq!(x_at(&clip, 3.0), 5.0);
+    }
+
+    #[test]
+    fn step_channels_hold_until_the_next_keyframe() {
+        let clip = clip(Interpolation::Step);
+        assert_eq!(x_at(&clip, 1.5), 0.0);
+        assert_eq!(x_at(&clip, 1.99), 0.0);
+        assert_eq!(x_at(&clip, 2.0), 4.0);
+        assert_eq!(x_at(&clip, 3.9), 4.0);
+    }
+
+    #[test]
+    fn channels_clamp_outside_their_keyframes() {
+        let clip = clip(Interpolation::Linear);
+        assert_eq!(x_at(&clip, 0.0), 0.0);
+        assert_eq!(x_at(&clip, 10.0), 6.0);
+    }
+
+    #[test]
+    fn sampling_leaves_unanimated_nodes_at_rest() {
+        let pose = clip(Interpolation::Linear).sample(&skeleton(), 1.5);
+        assert_eq!(pose[1], rest());
+    }
+
+    #[test]
+    fn rotation_channels_take_the_short_way_round() {
+        let clip = AnimationClip {
+            name: String::new(),
+            duration: 1.0,
+            channels: vec![Channel {
+                node: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                // The same as 190 degrees, but stored the long way round.
+                keyframes: Keyframes::Rotation(vec![turned(170.0), -turned(190.0)]),
+            }],
+        };
+        let rotation = clip.sample(&skeleton(), 0.5)[0].rotation;
+        assert!((rotation.dot(turned(180.0)).abs() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn animators_loop_or_stop_at_the_end() {
+        let clips = [clip(Interpolation::Linear)];
+        let mut animator = Animator::new(0);
+        animator.advance(5.0, &clips);
+        assert_eq!(animator.time, 1.0);
+
+        animator.looping = false;
+        animator.speed = 2.0;
+        animator.advance(5.0, &clips);
+        assert_eq!(animator.time, 4.0);
+    }
+}
@@ -2,58 +2,71 @@ use std::sync::Arc;
 
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalPosition,
-    event::{ElementState, KeyEvent, MouseButton, WindowEvent},
+    event::{DeviceEvent, DeviceId, ElementState, KeyEvent, MouseButton, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
+    keyboard::PhysicalKey,
     window::{CursorGrabMode, Window},
 };
 
+use crate::input::{Action, Binding, InputEvent};
+use crate::recording::InputLog;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::recording::Recording;
 use crate::state::State;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::wasm_bindgen;
 
+/// Scene loaded when no other is given on the command line.
+pub const DEFAULT_SCENE: &str = "scenes/default.ron";
+
 pub struct App {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<State>>,
     state: Option<State>,
+    window: Option<Arc<Window>>,
+    scene_path: String,
+    /// Handed to `State` once it's created.
+    input_log: Option<InputLog>,
     mouse_locked: bool,
 }
 
 impl App {
-    pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
+    pub fn new(
+        scene_path: String,
+        input_log: InputLog,
+        #[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>,
+    ) -> Self {
         #[cfg(target_arch = "wasm32")]
         let proxy = Some(event_loop.create_proxy());
         Self {
             state: None,
+            window: None,
+            scene_path,
+            input_log: Some(input_log),
             mouse_locked: false,
             #[cfg(target_arch = "wasm32")]
             proxy,
         }
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    fn try_lock_mouse(state: &State) -> bool {
-        center_cursor(state);
-
-        let lock_ok = state.window.set_cursor_grab(CursorGrabMode::Locked).is_ok()
-            || state
-                .window
-                .set_cursor_grab(CursorGrabMode::Confined)
-                .is_ok();
+    /// Hides the cursor and stops it leaving the window. Mouse look then reads
+    /// raw motion from `device_event`. On the web this requests pointer lock,
+    /// which the browser grants asynchronously.
+    fn try_lock_mouse(window: &Window) -> bool {
+        let lock_ok = window.set_cursor_grab(CursorGrabMode::Locked).is_ok()
+            || window.set_cursor_grab(CursorGrabMode::Confined).is_ok();
 
         if lock_ok {
-            state.window.set_cursor_visible(false);
+            window.set_cursor_visible(false);
         }
 
         lock_ok
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    fn unlock_mouse(state: &State) {
-        let _ = state.window.set_cursor_grab(CursorGrabMode::None);
-        state.window.set_cursor_visible(true);
+    fn unlock_mouse(window: &Window) {
+        let _ = window.set_cursor_grab(CursorGrabMode::None);
+        window.set_cursor_visible(true);
     }
 }
 
@@ -77,20 +90,24 @@ impl ApplicationHandler<State> for App {
         }
 
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+        self.window = Some(window.clone());
+        let input_log = self.input_log.take().unwrap_or(InputLog::Live);
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            self.state = Some(pollster::block_on(State::new(window)).unwrap());
+            self.state =
+                Some(pollster::block_on(State::new(window, &self.scene_path, input_log)).unwrap());
         }
 
         #[cfg(target_arch = "wasm32")]
         {
             if let Some(proxy) = self.proxy.take() {
+                let scene_path = self.scene_path.clone();
                 wasm_bindgen_futures::spawn_local(async move {
                     assert!(
                         proxy
                             .send_event(
-                                State::new(window)
+                                State::new(window, &scene_path, input_log)
                                     .await
                                     .expect("Unable to create canvas!!!")
                             )
@@ -104,12 +121,9 @@ impl ApplicationHandler<State> for App {
     #[allow(unused_mut)]
     fn user_event(&mut self, _event_loop: &ActiveEventLoop, mut event: State) {
         #[cfg(target_arch = "wasm32")]
-        {
-            event.window.request_redraw();
-            event.resize(
-                event.window.inner_size().width,
-                event.window.inner_size().height,
-            );
+        if let Some(window) = &self.window {
+            window.request_redraw();
+            event.resize(window.inner_size().width, window.inner_size().height);
         }
         self.state = Some(event);
     }
@@ -120,45 +134,53 @@ impl ApplicationHandler<State> for App {
         _window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
-        let state = match &mut self.state {
-            Some(canvas) => canvas,
-            None => return,
+        let (Some(state), Some(window)) = (&mut self.state, &self.window) else {
+            return;
         };
 
         match event {
             WindowEvent::Focused(is_focused) => {
+                // Browsers only grant pointer lock in response to a click.
                 if is_focused {
                     #[cfg(not(target_arch = "wasm32"))]
-                    {
-                        self.mouse_locked = Self::try_lock_mouse(state);
+                    if state.camera_mode().grabs_cursor() {
+                        self.mouse_locked = Self::try_lock_mouse(window);
                     }
                 } else {
-                    #[cfg(not(target_arch = "wasm32"))]
-                    {
-                        Self::unlock_mouse(state);
-                        self.mouse_locked = false;
-                    }
+                    Self::unlock_mouse(window);
+                    self.mouse_locked = false;
                 }
             }
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::Resized(size) => state.resize(size.width, size.height),
             WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state: ElementState::Pressed,
+                button,
+                state: button_state,
                 ..
-            } =>
-            {
-                #[cfg(not(target_arch = "wasm32"))]
-                if !self.mouse_locked {
-                    self.mouse_locked = Self::try_lock_mouse(state);
+            } => {
+                // Clicking into the window grabs the mouse for fly and follow
+                // modes; orbit mode drags with a visible cursor instead.
+                if button == MouseButton::Left
+                    && button_state == ElementState::Pressed
+                    && !still_locked(self.mouse_locked)
+                    && state.camera_mode().grabs_cursor()
+                {
+                    self.mouse_locked = Self::try_lock_mouse(window);
+                    return;
                 }
+                state.handle_input(InputEvent::MouseButton {
+                    button,
+                    pressed: button_state.is_pressed(),
+                });
             }
+            WindowEvent::MouseWheel { delta, .. } => state.handle_input(InputEvent::Scroll(delta)),
             WindowEvent::RedrawRequested => {
+                window.request_redraw();
                 state.update();
                 match state.render() {
                     Ok(_) => {}
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
+                        let size = window.inner_size();
                         state.resize(size.width, size.height);
                     }
                     Err(e) => {
@@ -175,25 +197,181 @@ impl ApplicationHandler<State> for App {
                     },
                 ..
             } => {
-                #[cfg(not(target_arch = "wasm32"))]
-                if code == KeyCode::Escape && key_state.is_pressed() && self.mouse_locked {
-                    Self::unlock_mouse(state);
+                // Escape frees the cursor before it can quit, unless it's
+                // being pressed to rebind something.
+                if key_state.is_pressed()
+                    && still_locked(self.mouse_locked)
+                    && !state.input().is_rebinding()
+                    && state
+                        .input()
+                        .map()
+                        .is_bound(Action::Quit, Binding::Key(code))
+                {
+                    Self::unlock_mouse(window);
                     self.mouse_locked = false;
                     return;
                 }
-                state.handle_key(event_loop, code, key_state.is_pressed())
+                let mode = state.camera_mode();
+                state.handle_input(InputEvent::Key {
+                    code,
+                    pressed: key_state.is_pressed(),
+                });
+                if state.camera_mode() != mode {
+                    if state.camera_mode().grabs_cursor() {
+                        self.mouse_locked = Self::try_lock_mouse(window);
+                    } else if self.mouse_locked {
+                        Self::unlock_mouse(window);
+                        self.mouse_locked = false;
+                    }
+                }
             }
             WindowEvent::CursorMoved { position, .. } => {
-                state.handle_mouse_moved(position.x, position.y);
-                if self.mouse_locked {
-                    center_cursor(state);
-                }
+                state.handle_input(InputEvent::CursorMoved {
+                    x: position.x,
+                    y: position.y,
+                })
             }
             _ => {}
         }
+
+        // Quitting from a gamepad or mouse frees a grabbed cursor first too,
+        // as Escape does above.
+        if state.quit_requested() {
+            if still_locked(self.mouse_locked) {
+                Self::unlock_mouse(window);
+                self.mouse_locked = false;
+                state.cancel_quit();
+            } else {
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(state) = &mut self.state
+            && let Err(e) = state.finish_input_log()
+        {
+            log::error!("Unable to save input recording: {}", e);
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let DeviceEvent::MouseMotion { delta } = event
+            && still_locked(self.mouse_locked)
+            && let Some(state) = &mut self.state
+        {
+            state.handle_input(InputEvent::MouseMotion {
+                delta_x: delta.0,
+                delta_y: delta.1,
+            });
+        }
     }
 }
 
+/// What to run, from the command line.
+#[cfg(not(target_arch = "wasm32"))]
+struct Args {
+    scene_path: String,
+    input_log: InputLog,
+    /// Render one frame to this PNG without opening a window, then exit.
+    headless: Option<std::path::PathBuf>,
+    /// Size of the headless frame.
+    size: (u32, u32),
+}
+
+/// `[scene] [--record <file> | --replay <file>] [--headless <png> [--size WxH]]`.
+/// A replay runs the scene it was recorded in.
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Args> {
+    use anyhow::Context;
+
+    let mut scene_path = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut headless = None;
+    let mut size = (1280, 720);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = Some(args.next().context("--record needs a file")?),
+            "--replay" => replay = Some(args.next().context("--replay needs a file")?),
+            "--headless" => headless = Some(args.next().context("--headless needs a file")?),
+            "--size" => {
+                let value = args.next().context("--size needs WIDTHxHEIGHT")?;
+                size = value
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .with_context(|| format!("Bad --size {:?}, expected WIDTHxHEIGHT", value))?;
+            }
+            flag if flag.starts_with("--") => anyhow::bail!("Unknown option {:?}", flag),
+            _ => scene_path = Some(arg),
+        }
+    }
+    let headless = headless.map(Into::into);
+
+    if let Some(path) = replay {
+        let recording = Recording::load(path.as_ref())?;
+        if let Some(scene_path) = scene_path {
+            log::warn!(
+                "Ignoring scene {:?}; the replay runs {:?}",
+                scene_path,
+                recording.scene
+            );
+        }
+        return Ok(Args {
+            scene_path: recording.scene.clone(),
+            input_log: InputLog::replay(recording),
+            headless,
+            size,
+        });
+    }
+    let scene_path = scene_path.unwrap_or_else(|| DEFAULT_SCENE.to_string());
+    let input_log = match record {
+        Some(path) => InputLog::record(path.into(), &scene_path),
+        None => InputLog::Live,
+    };
+    Ok(Args {
+        scene_path,
+        input_log,
+        headless,
+        size,
+    })
+}
+
+/// Whether a lock taken with `App::try_lock_mouse` still holds.
+#[cfg(not(target_arch = "wasm32"))]
+fn still_locked(mouse_locked: bool) -> bool {
+    mouse_locked
+}
+
+/// Browsers release pointer lock on Escape or when it's denied, without
+/// telling winit, so ask the page too.
+#[cfg(target_arch = "wasm32")]
+fn still_locked(mouse_locked: bool) -> bool {
+    mouse_locked
+        && wgpu::web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.pointer_lock_element())
+            .is_some()
+}
+
+/// Renders the first frame, or the end of a replay, to `path`.
+#[cfg(not(target_arch = "wasm32"))]
+fn render_headless(mut state: State, path: &std::path::Path) -> anyhow::Result<()> {
+    while state.replaying() {
+        state.advance(1.0 / 60.0);
+    }
+    state.advance(0.0);
+    state.capture()?.save(path)?;
+    log::info!("Saved {:?}", path);
+    Ok(())
+}
+
 pub fn run() -> anyhow::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -204,8 +382,28 @@ pub fn run() -> anyhow::Result<()> {
         console_log::init_with_level(log::Level::Info).unwrap();
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    let (scene_path, input_log) = {
+        let args = parse_args(std::env::args().skip(1))?;
+        if let Some(path) = &args.headless {
+            let (width, height) = args.size;
+            let state = pollster::block_on(State::new_headless(
+                width,
+                height,
+                &args.scene_path,
+                args.input_log,
+            ))?;
+            return render_headless(state, path);
+        }
+        (args.scene_path, args.input_log)
+    };
+    #[cfg(target_arch = "wasm32")]
+    let (scene_path, input_log) = (DEFAULT_SCENE.to_string(), InputLog::Live);
+
     let event_loop = EventLoop::with_user_event().build()?;
     let mut app = App::new(
+        scene_path,
+        input_log,
         #[cfg(target_arch = "wasm32")]
         &event_loop,
     );
@@ -214,13 +412,6 @@ pub fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn center_cursor(state: &State) {
-    let size = state.window.inner_size();
-    let center = PhysicalPosition::new(size.width as f64 * 0.5, size.height as f64 * 0.5);
-    let _ = state.window.set_cursor_position(center);
-}
-
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
 pub fn run_web() -> Result<(), wasm_bindgen::JsValue> {
@@ -229,3 +420,32 @@ pub fn run_web() -> Result<(), wasm_bindgen::JsValue> {
 
     Ok(())
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parse_args_rejects_unknown_options() {
+        assert!(parse_args(args(&["--recrod", "session.ron"])).is_err());
+        assert!(parse_args(args(&["--record"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_records_the_chosen_scene() {
+        let parsed = parse_args(args(&["scenes/island.ron", "--record", "session.ron"])).unwrap();
+        assert_eq!(parsed.scene_path, "scenes/island.ron");
+        assert!(matches!(parsed.input_log, InputLog::Record { .. }));
+
+        let parsed = parse_args(args(&[])).unwrap();
+        assert_eq!(parsed.scene_path, DEFAULT_SCENE);
+        assert!(matches!(parsed.input_log, InputLog::Live));
+    }
+}
@@ -0,0 +1,10 @@
+// Draws a texture over the whole target.
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Source and target are the same size, so pixels line up one to one.
+    return textureLoad(t_source, vec2<i32>(in.clip_position.xy), 0);
+}
@@ -0,0 +1,491 @@
+use serde::Deserialize;
+
+use crate::fullscreen;
+use crate::hdr::HdrPipeline;
+
+/// Bloom's look. Read every frame, so changes apply straight away.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// How much of the final image is the blurred glow, from 0 to 1.
+    pub intensity: f32,
+    /// Brightness below which pixels don't glow. At 0 everything glows a
+    /// little, as light scattering in a real lens does.
+    pub threshold: f32,
+    /// Spread of each blur step, as a fraction of the screen height.
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.04,
+            threshold: 0.0,
+            radius: 0.005,
+        }
+    }
+}
+
+/// A `BloomSettings` value the bloom keys adjust.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BloomControl {
+    Intensity,
+    Threshold,
+    Radius,
+}
+
+/// Added to `intensity` per press.
+const INTENSITY_STEP: f32 = 0.01;
+/// Added to `threshold` per press.
+const THRESHOLD_STEP: f32 = 0.25;
+/// `radius` is multiplied or divided by this per press, as small radii need
+/// finer steps than large ones.
+const RADIUS_FACTOR: f32 = 1.25;
+const RADIUS_RANGE: (f32, f32) = (0.001, 0.05);
+
+impl BloomSettings {
+    /// Moves `control` one step up or down, keeping it in range.
+    pub fn step(&mut self, control: BloomControl, up: bool) {
+        let sign = if up { 1.0 } else { -1.0 };
+        match control {
+            BloomControl::Intensity => {
+                self.intensity = (self.intensity + sign * INTENSITY_STEP).clamp(0.0, 1.0);
+            }
+            BloomControl::Threshold => {
+                self.threshold = (self.threshold + sign * THRESHOLD_STEP).max(0.0);
+            }
+            BloomControl::Radius => {
+                self.radius =
+                    (self.radius * RADIUS_FACTOR.powf(sign)).clamp(RADIUS_RANGE.0, RADIUS_RANGE.1);
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    _padding: f32,
+}
+
+/// What `HdrPipeline` needs to blend the bloom in.
+pub struct BloomOutput<'a> {
+    /// Laid out as `HdrPipeline::bloom_layout`.
+    pub bind_group: &'a wgpu::BindGroup,
+    pub intensity: f32,
+    /// Every level ends up summed into the bloom texture, so it's divided by
+    /// this to average them.
+    pub levels: u32,
+}
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Most levels in the chain. Each halves the size of the one before.
+const MAX_LEVELS: usize = 6;
+/// Levels stop before either side would drop below this many pixels.
+const MIN_LEVEL_SIZE: u32 = 8;
+
+/// Blurs the bright parts of the HDR target through a chain of half-size
+/// levels, for `HdrPipeline` to blend in while tonemapping.
+pub struct Bloom {
+    pub settings: BloomSettings,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    levels: Levels,
+}
+
+/// Everything that depends on the screen size.
+struct Levels {
+    views: Vec<wgpu::TextureView>,
+    /// Reads the HDR target, for the first level.
+    prefilter_bind_group: wgpu::BindGroup,
+    /// `sources[i]` reads `views[i]`.
+    sources: Vec<wgpu::BindGroup>,
+    /// The finished bloom, for compositing.
+    output: wgpu::BindGroup,
+}
+
+impl Bloom {
+    /// `None` where the adapter can't filter and blend `Rgba16Float` render
+    /// targets, as on some WebGL2 devices. The frame is then drawn without.
+    pub fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::Adapter,
+        hdr: &HdrPipeline,
+        width: u32,
+        height: u32,
+        settings: BloomSettings,
+    ) -> Option<Self> {
+        let features = adapter.get_texture_format_features(FORMAT);
+        let supported = hdr.format() == FORMAT
+            && features.allowed_usages.contains(
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            )
+            && features.flags.contains(
+                wgpu::TextureFormatFeatureFlags::FILTERABLE
+                    | wgpu::TextureFormatFeatureFlags::BLENDABLE,
+            );
+        if !supported {
+            log::warn!("Bloom needs filterable, blendable Rgba16Float targets; skipping it");
+            return None;
+        }
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("bloom_bind_group_layout"),
+        });
+
+        let shader = fullscreen::create_shader(device, "Bloom Shader", include_str!("bloom.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let prefilter_pipeline =
+            create_pipeline(device, &pipeline_layout, &shader, "fs_prefilter", None);
+        let downsample_pipeline =
+            create_pipeline(device, &pipeline_layout, &shader, "fs_downsample", None);
+        let upsample_pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            "fs_upsample",
+            Some(additive),
+        );
+
+        let levels = Levels::new(
+            device,
+            hdr,
+            &bind_group_layout,
+            &sampler,
+            &uniform_buffer,
+            width,
+            height,
+        );
+
+        Some(Self {
+            settings,
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            levels,
+        })
+    }
+
+    /// Call after `HdrPipeline::resize`, as the first level reads its target.
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &HdrPipeline, width: u32, height: u32) {
+        self.levels = Levels::new(
+            device,
+            hdr,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            width,
+            height,
+        );
+    }
+
+    /// Uploads `settings`, for the next `render`.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let uniform = BloomUniform {
+            threshold: self.settings.threshold,
+            knee: self.settings.threshold * 0.5,
+            radius: self.settings.radius,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// The finished bloom, once `render` has run. `None` while disabled.
+    pub fn output(&self) -> Option<BloomOutput<'_>> {
+        self.settings.enabled.then_some(BloomOutput {
+            bind_group: &self.levels.output,
+            intensity: self.settings.intensity,
+            levels: self.levels.views.len() as u32,
+        })
+    }
+
+    /// Filters the HDR target down the chain and back up. Does nothing while
+    /// disabled.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.settings.enabled {
+            return;
+        }
+        let levels = &self.levels;
+
+        run_pass(
+            encoder,
+            "Bloom Prefilter Pass",
+            &self.prefilter_pipeline,
+            &levels.prefilter_bind_group,
+            &levels.views[0],
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+        );
+        for level in 1..levels.views.len() {
+            run_pass(
+                encoder,
+                "Bloom Downsample Pass",
+                &self.downsample_pipeline,
+                &levels.sources[level - 1],
+                &levels.views[level],
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            );
+        }
+        for level in (1..levels.views.len()).rev() {
+            run_pass(
+                encoder,
+                "Bloom Upsample Pass",
+                &self.upsample_pipeline,
+                &levels.sources[level],
+                &levels.views[level - 1],
+                wgpu::LoadOp::Load,
+            );
+        }
+    }
+}
+
+impl Levels {
+    fn new(
+        device: &wgpu::Device,
+        hdr: &HdrPipeline,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let mut views = Vec::new();
+        let (mut level_width, mut level_height) = ((width / 2).max(1), (height / 2).max(1));
+        loop {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("bloom_level"),
+                size: wgpu::Extent3d {
+                    width: level_width,
+                    height: level_height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            views.push(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+            level_width /= 2;
+            level_height /= 2;
+            if views.len() == MAX_LEVELS || level_width.min(level_height) < MIN_LEVEL_SIZE {
+                break;
+            }
+        }
+
+        let bind_group = |view: &wgpu::TextureView, label| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some(label),
+            })
+        };
+        let prefilter_bind_group = bind_group(hdr.view(), "bloom_prefilter_bind_group");
+        let sources = views
+            .iter()
+            .map(|view| bind_group(view, "bloom_level_bind_group"))
+            .collect();
+
+        let output = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: hdr.bloom_layout(),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("bloom_output_bind_group"),
+        });
+
+        Self {
+            views,
+            prefilter_bind_group,
+            sources,
+            output,
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    fullscreen::create_pipeline(
+        device,
+        fragment_entry_point,
+        layout,
+        shader,
+        fragment_entry_point,
+        wgpu::ColorTargetState {
+            format: FORMAT,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        },
+    )
+}
+
+fn run_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    source: &wgpu::BindGroup,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            depth_slice: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+        multiview_mask: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, source, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_move_each_setting_and_undo() {
+        let mut settings = BloomSettings::default();
+        for control in [
+            BloomControl::Intensity,
+            BloomControl::Threshold,
+            BloomControl::Radius,
+        ] {
+            settings.step(control, true);
+            settings.step(control, false);
+        }
+        let default = BloomSettings::default();
+        assert!((settings.intensity - default.intensity).abs() < 1e-6);
+        assert_eq!(settings.threshold, default.threshold);
+        assert!((settings.radius - default.radius).abs() < 1e-6);
+
+        settings.step(BloomControl::Intensity, true);
+        assert!(settings.intensity > default.intensity);
+        settings.step(BloomControl::Radius, true);
+        assert!(settings.radius > default.radius);
+    }
+
+    #[test]
+    fn steps_stop_at_the_ends_of_each_range() {
+        let mut settings = BloomSettings::default();
+        for _ in 0..200 {
+            settings.step(BloomControl::Intensity, false);
+            settings.step(BloomControl::Threshold, false);
+            settings.step(BloomControl::Radius, false);
+        }
+        assert_eq!(settings.intensity, 0.0);
+        assert_eq!(settings.threshold, 0.0);
+        assert_eq!(settings.radius, RADIUS_RANGE.0);
+
+        for _ in 0..200 {
+            settings.step(BloomControl::Intensity, true);
+            settings.step(BloomControl::Radius, true);
+        }
+        assert_eq!(settings.intensity, 1.0);
+        assert_eq!(settings.radius, RADIUS_RANGE.1);
+    }
+}
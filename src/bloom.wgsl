@@ -0,0 +1,126 @@
+// Physically based bloom after Jorge Jimenez's "Next Generation Post
+// Processing in Call of Duty: Advanced Warfare": the HDR image is filtered
+// down a chain of half-size targets, then filtered back up with each level
+// added onto the one above.
+
+struct Bloom {
+    threshold: f32,
+    // Width of the soft ramp below `threshold`.
+    knee: f32,
+    // Tent filter radius for upsampling, as a fraction of the screen height.
+    radius: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+
+fn sample_offset(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec3<f32> {
+    return textureSample(source, source_sampler, uv + texel * vec2<f32>(x, y)).rgb;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Weight that keeps single very bright pixels from flickering into large
+// blobs (Brian Karis's average).
+fn karis_weight(color: vec3<f32>) -> f32 {
+    return 1.0 / (1.0 + luminance(color));
+}
+
+// Scales `color` down smoothly as its brightest channel falls below
+// `threshold`. With a threshold of 0 everything passes unchanged.
+fn apply_threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 1e-4);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 1e-4);
+    return color * contribution;
+}
+
+// The 13 taps of the downsample filter, as five overlapping 2x2 boxes.
+struct Boxes {
+    centre: vec3<f32>,
+    top_left: vec3<f32>,
+    top_right: vec3<f32>,
+    bottom_left: vec3<f32>,
+    bottom_right: vec3<f32>,
+}
+
+fn downsample_boxes(uv: vec2<f32>) -> Boxes {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let a = sample_offset(uv, texel, -2.0, -2.0);
+    let b = sample_offset(uv, texel, 0.0, -2.0);
+    let c = sample_offset(uv, texel, 2.0, -2.0);
+    let d = sample_offset(uv, texel, -2.0, 0.0);
+    let e = sample_offset(uv, texel, 0.0, 0.0);
+    let f = sample_offset(uv, texel, 2.0, 0.0);
+    let g = sample_offset(uv, texel, -2.0, 2.0);
+    let h = sample_offset(uv, texel, 0.0, 2.0);
+    let i = sample_offset(uv, texel, 2.0, 2.0);
+    let j = sample_offset(uv, texel, -1.0, -1.0);
+    let k = sample_offset(uv, texel, 1.0, -1.0);
+    let l = sample_offset(uv, texel, -1.0, 1.0);
+    let m = sample_offset(uv, texel, 1.0, 1.0);
+
+    var boxes: Boxes;
+    boxes.centre = (j + k + l + m) * 0.25;
+    boxes.top_left = (a + b + d + e) * 0.25;
+    boxes.top_right = (b + c + e + f) * 0.25;
+    boxes.bottom_left = (d + e + g + h) * 0.25;
+    boxes.bottom_right = (e + f + h + i) * 0.25;
+    return boxes;
+}
+
+// First step down from the HDR image: thresholds, and averages the boxes by
+// Karis weight so fireflies don't bloom.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let boxes = downsample_boxes(in.uv);
+    let centre = apply_threshold(boxes.centre);
+    let top_left = apply_threshold(boxes.top_left);
+    let top_right = apply_threshold(boxes.top_right);
+    let bottom_left = apply_threshold(boxes.bottom_left);
+    let bottom_right = apply_threshold(boxes.bottom_right);
+
+    let w0 = 0.5 * karis_weight(centre);
+    let w1 = 0.125 * karis_weight(top_left);
+    let w2 = 0.125 * karis_weight(top_right);
+    let w3 = 0.125 * karis_weight(bottom_left);
+    let w4 = 0.125 * karis_weight(bottom_right);
+    let color = centre * w0 + top_left * w1 + top_right * w2 + bottom_left * w3
+        + bottom_right * w4;
+    return vec4<f32>(color / (w0 + w1 + w2 + w3 + w4), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let boxes = downsample_boxes(in.uv);
+    let color = boxes.centre * 0.5
+        + (boxes.top_left + boxes.top_right + boxes.bottom_left + boxes.bottom_right) * 0.125;
+    return vec4<f32>(color, 1.0);
+}
+
+// 3x3 tent filter. Blended additively onto the next larger level.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source));
+    let radius = vec2<f32>(bloom.radius * size.y / size.x, bloom.radius);
+    let a = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(-1.0, -1.0)).rgb;
+    let b = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(0.0, -1.0)).rgb;
+    let c = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(1.0, -1.0)).rgb;
+    let d = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(-1.0, 0.0)).rgb;
+    let e = textureSample(source, source_sampler, in.uv).rgb;
+    let f = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(1.0, 0.0)).rgb;
+    let g = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(-1.0, 1.0)).rgb;
+    let h = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(0.0, 1.0)).rgb;
+    let i = textureSample(source, source_sampler, in.uv + radius * vec2<f32>(1.0, 1.0)).rgb;
+
+    let color = e * 4.0 + (b + d + f + h) * 2.0 + (a + c + g + i);
+    return vec4<f32>(color / 16.0, 1.0);
+}
@@ -1,22 +1,191 @@
-use cgmath::{InnerSpace, Rotation, Rotation3, SquareMatrix};
-use winit::keyboard::KeyCode;
+use cgmath::{EuclideanSpace, InnerSpace, Rotation, Rotation3, SquareMatrix};
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
 
+use crate::collision::Ray;
+use crate::input::{Action, Axis, Input};
+use crate::texture::DepthMode;
+
+/// Pitch stays just short of straight up or down, where the view would flip
+/// over.
+const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Projection {
+    Perspective {
+        aspect: f32,
+        /// Vertical field of view in degrees.
+        fovy: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    Orthographic {
+        aspect: f32,
+        /// World units visible from the bottom to the top of the view.
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl Projection {
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let new_aspect = width as f32 / height as f32;
+        match self {
+            Projection::Perspective { aspect, .. } | Projection::Orthographic { aspect, .. } => {
+                *aspect = new_aspect;
+            }
+        }
+    }
+
+    /// Shrinks or grows an orthographic view by `factor`. Perspective views
+    /// zoom by moving the camera instead, so this leaves them alone.
+    pub fn zoom(&mut self, factor: f32) {
+        if let Projection::Orthographic { height, .. } = self {
+            *height *= factor;
+        }
+    }
+
+    /// Projection into wgpu clip space. With reversed depth a perspective
+    /// projection puts the far plane at infinity and ignores `zfar`.
+    pub fn build_matrix(&self, depth_mode: DepthMode) -> cgmath::Matrix4<f32> {
+        match (*self, depth_mode) {
+            (
+                Projection::Perspective {
+                    aspect,
+                    fovy,
+                    znear,
+                    ..
+                },
+                DepthMode::Reversed,
+            ) => {
+                let f = 1.0 / (cgmath::Rad::from(cgmath::Deg(fovy)).0 / 2.0).tan();
+                cgmath::Matrix4::from_cols(
+                    cgmath::Vector4::new(f / aspect, 0.0, 0.0, 0.0),
+                    cgmath::Vector4::new(0.0, f, 0.0, 0.0),
+                    cgmath::Vector4::new(0.0, 0.0, 0.0, -1.0),
+                    cgmath::Vector4::new(0.0, 0.0, znear, 0.0),
+                )
+            }
+            (
+                Projection::Perspective {
+                    aspect,
+                    fovy,
+                    znear,
+                    zfar,
+                },
+                DepthMode::Standard,
+            ) => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(fovy), aspect, znear, zfar)
+            }
+            (
+                Projection::Orthographic {
+                    aspect,
+                    height,
+                    znear,
+                    zfar,
+                },
+                depth_mode,
+            ) => {
+                let half_height = height * 0.5;
+                let half_width = half_height * aspect;
+                let proj = OPENGL_TO_WGPU_MATRIX
+                    * cgmath::ortho(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        znear,
+                        zfar,
+                    );
+                match depth_mode {
+                    DepthMode::Standard => proj,
+                    DepthMode::Reversed => REVERSE_Z_MATRIX * proj,
+                }
+            }
+        }
+    }
+}
+
+/// A first-person camera. Yaw is measured from +X towards +Z and pitch up from
+/// the horizon, so the view is rebuilt from two angles each frame rather than
+/// accumulating rotations.
+#[derive(Copy, Clone, Debug)]
 pub struct Camera {
-    pub eye: cgmath::Point3<f32>,
-    pub target: cgmath::Point3<f32>,
-    pub up: cgmath::Vector3<f32>,
-    pub aspect: f32,
-    pub fovy: f32,
-    pub znear: f32,
-    pub zfar: f32,
+    pub position: cgmath::Point3<f32>,
+    pub yaw: cgmath::Rad<f32>,
+    pub pitch: cgmath::Rad<f32>,
+    pub projection: Projection,
+    pub depth_mode: DepthMode,
 }
 
 impl Camera {
+    /// A camera at `position` facing `target`.
+    pub fn looking_at(
+        position: cgmath::Point3<f32>,
+        target: cgmath::Point3<f32>,
+        projection: Projection,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let mut camera = Self {
+            position,
+            yaw: cgmath::Rad(0.0),
+            pitch: cgmath::Rad(0.0),
+            projection,
+            depth_mode,
+        };
+        camera.look_at(target);
+        camera
+    }
+
+    /// Turns the camera to face `target` without moving it.
+    pub fn look_at(&mut self, target: cgmath::Point3<f32>) {
+        let direction = target - self.position;
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+        let direction = direction.normalize();
+        self.yaw = cgmath::Rad(direction.z.atan2(direction.x));
+        self.pitch = cgmath::Rad(direction.y.asin());
+        self.clamp_pitch();
+    }
+
+    /// Blends towards `other`, turning the short way round. The projection is
+    /// taken from `other`.
+    pub fn lerp(&self, other: &Camera, factor: f32) -> Camera {
+        let yaw_change = (other.yaw.0 - self.yaw.0 + PI).rem_euclid(TAU) - PI;
+        Camera {
+            position: self.position + (other.position - self.position) * factor,
+            yaw: cgmath::Rad(self.yaw.0 + yaw_change * factor),
+            pitch: cgmath::Rad(self.pitch.0 + (other.pitch.0 - self.pitch.0) * factor),
+            ..*other
+        }
+    }
+
+    fn clamp_pitch(&mut self) {
+        self.pitch.0 = self.pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+    }
+
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        cgmath::Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
+    }
+
+    /// Horizontal unit vector to the camera's right.
+    pub fn right(&self) -> cgmath::Vector3<f32> {
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        cgmath::Vector3::new(-sin_yaw, 0.0, cos_yaw)
+    }
+
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_to_rh(self.position, self.forward(), cgmath::Vector3::unit_y())
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let view = self.build_view_matrix();
+        let proj = self.projection.build_matrix(self.depth_mode);
 
-        OPENGL_TO_WGPU_MATRIX * proj * view
+        proj * view
     }
 }
 
@@ -28,126 +197,252 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
     cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
 );
 
+/// Maps wgpu depth `z` to `1 - z`.
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
+    cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, -1.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, 1.0, 1.0),
+);
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    pub view_position: [f32; 4],
     pub view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Free movement and mouse-look, driven by `CameraController`.
+    Fly,
+    /// Circling a focus point, driven by `OrbitController`.
+    Orbit,
+    /// Trailing a scene node, driven by `FollowController`.
+    Follow,
+}
+
+impl CameraMode {
+    /// Whether the mode steers with relative mouse movement and so wants the
+    /// cursor grabbed.
+    pub fn grabs_cursor(self) -> bool {
+        match self {
+            CameraMode::Fly | CameraMode::Follow => true,
+            CameraMode::Orbit => false,
+        }
+    }
+}
+
 pub struct CameraController {
     speed: f32,
     mouse_speed: f32,
-    horizontal: f32,
-    vertical: f32,
-    mouse_x: f32,
-    mouse_y: f32,
 }
 
 impl CameraController {
     pub fn new(speed: f32, mouse_speed: f32) -> Self {
+        Self { speed, mouse_speed }
+    }
+
+    pub fn update_camera(&self, camera: &mut Camera, input: &Input, delta: f32) {
+        camera.position += camera.forward() * self.speed * input.axis(Axis::MoveY) * delta;
+        camera.position += camera.right() * self.speed * input.axis(Axis::MoveX) * delta;
+
+        camera.yaw += cgmath::Rad(input.axis(Axis::LookX) * self.mouse_speed * delta);
+        camera.pitch -= cgmath::Rad(input.axis(Axis::LookY) * self.mouse_speed * delta);
+        // Keep yaw small so it doesn't lose precision after many turns.
+        camera.yaw.0 = camera.yaw.0.rem_euclid(TAU);
+        camera.clamp_pitch();
+    }
+}
+
+/// Orbits the camera around a focus point: dragging with `OrbitRotate` held
+/// rotates, with `OrbitPan` held pans, and the `Zoom` axis zooms.
+pub struct OrbitController {
+    focus: cgmath::Point3<f32>,
+    distance: f32,
+    rotate_speed: f32,
+    zoom_speed: f32,
+}
+
+impl OrbitController {
+    const MIN_DISTANCE: f32 = 0.1;
+    const MAX_DISTANCE: f32 = 500.0;
+    /// Focus movement per pixel of panning, per unit of distance.
+    const PAN_SPEED: f32 = 0.001;
+
+    pub fn new(distance: f32, rotate_speed: f32, zoom_speed: f32) -> Self {
         Self {
-            speed,
-            mouse_speed,
-            horizontal: 0.0,
-            vertical: 0.0,
-            mouse_x: 0.0,
-            mouse_y: 0.0,
+            focus: (0.0, 0.0, 0.0).into(),
+            distance,
+            rotate_speed,
+            zoom_speed,
         }
     }
 
-    pub fn handle_key(&mut self, code: KeyCode, is_pressed: bool) -> bool {
-        match code {
-            KeyCode::KeyW | KeyCode::ArrowUp => {
-                self.vertical = if is_pressed {
-                    1.0
-                } else if self.vertical != -1.0 {
-                    0.0
-                } else {
-                    self.vertical
-                };
-                true
-            }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
-                self.horizontal = if is_pressed {
-                    -1.0
-                } else if self.horizontal != 1.0 {
-                    0.0
-                } else {
-                    self.horizontal
-                };
-                true
-            }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
-                self.vertical = if is_pressed {
-                    -1.0
-                } else if self.vertical != 1.0 {
-                    0.0
-                } else {
-                    self.vertical
-                };
-                true
-            }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
-                self.horizontal = if is_pressed {
-                    1.0
-                } else if self.horizontal != -1.0 {
-                    0.0
-                } else {
-                    self.horizontal
-                };
-                true
-            }
-            _ => false,
+    /// Puts the focus point straight ahead of `camera`, so switching to this
+    /// controller doesn't move the view.
+    pub fn focus_ahead(&mut self, camera: &Camera) {
+        self.focus = camera.position + camera.forward() * self.distance;
+    }
+
+    /// The look axes are read as pixels dragged over a tick of `delta`
+    /// seconds, and the zoom axis as lines scrolled.
+    pub fn update_camera(&mut self, camera: &mut Camera, input: &Input, delta: f32) {
+        let drag_x = input.axis_movement(Axis::LookX, delta);
+        let drag_y = input.axis_movement(Axis::LookY, delta);
+        if input.is_pressed(Action::OrbitRotate) {
+            camera.yaw += cgmath::Rad(drag_x * self.rotate_speed);
+            camera.pitch -= cgmath::Rad(drag_y * self.rotate_speed);
+            camera.yaw.0 = camera.yaw.0.rem_euclid(TAU);
+            camera.clamp_pitch();
+        } else if input.is_pressed(Action::OrbitPan) {
+            let right = camera.right();
+            let up = right.cross(camera.forward());
+            let scale = Self::PAN_SPEED * self.distance;
+            self.focus += (up * drag_y - right * drag_x) * scale;
+        }
+
+        let zoom = (1.0 - self.zoom_speed).powf(input.axis_movement(Axis::Zoom, delta));
+        let distance = (self.distance * zoom).clamp(Self::MIN_DISTANCE, Self::MAX_DISTANCE);
+        camera.projection.zoom(distance / self.distance);
+        self.distance = distance;
+        camera.position = self.focus - camera.forward() * self.distance;
+    }
+}
+
+/// Trails a target at an offset from it, easing towards that spot and pulling
+/// in when something blocks the view of the target.
+pub struct FollowController {
+    /// Camera position relative to the target, turned with the target's
+    /// heading. +Z is behind a target facing -Z.
+    offset: cgmath::Vector3<f32>,
+    /// Height above the target's origin that the camera looks at.
+    look_height: f32,
+    /// How quickly the camera catches up; higher is snappier.
+    stiffness: f32,
+    mouse_speed: f32,
+    /// Extra turn around the target from the mouse.
+    yaw: cgmath::Rad<f32>,
+    position: Option<cgmath::Point3<f32>>,
+}
+
+impl FollowController {
+    /// Gap kept between the camera and whatever it's pulled in against.
+    const CLEARANCE: f32 = 0.2;
+    const MIN_DISTANCE: f32 = 0.3;
+
+    pub fn new(
+        offset: cgmath::Vector3<f32>,
+        look_height: f32,
+        stiffness: f32,
+        mouse_speed: f32,
+    ) -> Self {
+        Self {
+            offset,
+            look_height,
+            stiffness,
+            mouse_speed,
+            yaw: cgmath::Rad(0.0),
+            position: None,
         }
     }
 
-    pub fn handle_mouse_moved(&mut self, delta_x: f32, delta_y: f32) {
-        self.mouse_x += delta_x;
-        self.mouse_y += delta_y;
+    /// Jump straight to the desired position on the next update instead of
+    /// easing in from wherever the camera was.
+    pub fn reset(&mut self) {
+        self.position = None;
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, delta: f32) {
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
+    /// `target` is the followed node's world matrix. `raycast` returns the
+    /// distance along a ray to the first obstacle closer than the given
+    /// maximum.
+    pub fn update_camera(
+        &mut self,
+        camera: &mut Camera,
+        target: cgmath::Matrix4<f32>,
+        input: &Input,
+        delta: f32,
+        raycast: impl Fn(&Ray, f32) -> Option<f32>,
+    ) {
+        self.yaw -= cgmath::Rad(input.axis(Axis::LookX) * self.mouse_speed * delta);
+        self.yaw.0 = self.yaw.0.rem_euclid(TAU);
 
-        let forward_vec = forward_norm * self.speed * self.vertical * delta;
-        camera.eye += forward_vec;
-        camera.target += forward_vec;
+        let target_position = cgmath::Point3::from_vec(target.w.truncate());
+        // Only the target's heading matters; pitch, roll and scale would
+        // swing the camera around with it.
+        let heading = cgmath::Rad((-target.x.z).atan2(target.x.x));
+        let rotation = cgmath::Quaternion::from_angle_y(heading + self.yaw);
+        let desired = target_position + rotation.rotate_vector(self.offset);
+        let pivot = target_position + cgmath::Vector3::unit_y() * self.look_height;
 
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
+        let mut position = match self.position {
+            Some(position) => {
+                let blend = 1.0 - (-self.stiffness * delta).exp();
+                position + (desired - position) * blend
+            }
+            None => desired,
+        };
+
+        let to_camera = position - pivot;
+        let distance = to_camera.magnitude();
+        if distance > Self::MIN_DISTANCE {
+            let ray = Ray {
+                origin: pivot,
+                direction: to_camera / distance,
+            };
+            if let Some(hit) = raycast(&ray, distance + Self::CLEARANCE) {
+                let pulled_in = (hit - Self::CLEARANCE).clamp(Self::MIN_DISTANCE, distance);
+                position = ray.at(pulled_in);
+            }
+        }
 
-        let right = forward_norm.cross(camera.up).normalize();
-        let right_vec = right * self.speed * self.horizontal * delta;
-        camera.eye += right_vec;
-        camera.target += right_vec;
+        self.position = Some(position);
+        camera.position = position;
+        camera.look_at(pivot);
+    }
+}
 
-        let forward = camera.target - camera.eye;
-        let yaw = cgmath::Rad(-self.mouse_x * self.mouse_speed * delta);
-        let rotation = cgmath::Quaternion::from_axis_angle(camera.up.normalize(), yaw);
-        let rotated_forward = rotation.rotate_vector(forward);
-        camera.target = camera.eye + rotated_forward;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let forward = camera.target - camera.eye;
-        let right = forward_norm.cross(camera.up).normalize();
-        let pitch = cgmath::Rad(-self.mouse_y * self.mouse_speed * delta);
-        let rotation = cgmath::Quaternion::from_axis_angle(right, pitch);
-        let rotated_forward = rotation.rotate_vector(forward);
-        camera.target = camera.eye + rotated_forward;
+    fn facing(yaw: f32) -> Camera {
+        Camera {
+            position: cgmath::Point3::new(0.0, 0.0, 0.0),
+            yaw: cgmath::Deg(yaw).into(),
+            pitch: cgmath::Rad(0.0),
+            projection: Projection::Perspective {
+                aspect: 1.0,
+                fovy: 45.0,
+                znear: 0.1,
+                zfar: 100.0,
+            },
+            depth_mode: DepthMode::Standard,
+        }
+    }
 
-        self.mouse_x = 0.0;
-        self.mouse_y = 0.0;
+    #[test]
+    fn lerp_turns_the_short_way_across_the_wrap() {
+        let blended = facing(170.0).lerp(&facing(-170.0), 0.5);
+        assert!((blended.yaw.0 - PI).abs() < 1e-5);
+        let blended = facing(-170.0).lerp(&facing(170.0), 0.5);
+        assert!((blended.yaw.0 + PI).abs() < 1e-5);
+        let blended = facing(10.0).lerp(&facing(-30.0), 0.25);
+        assert!(blended.yaw.0.abs() < 1e-5);
     }
 }
@@ -0,0 +1,252 @@
+use crate::fullscreen;
+
+/// Copies `texture` back from the GPU, blocking until it arrives. The texture
+/// needs `COPY_SRC` usage and an 8-bit RGBA or BGRA format.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<image::RgbaImage> {
+    use wgpu::TextureFormat;
+
+    let swap_red_blue = match texture.format() {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+        format => anyhow::bail!("Can't read back {:?} textures", format),
+    };
+
+    let width = texture.width();
+    let height = texture.height();
+    // Rows in the buffer have to start on a 256 byte boundary.
+    let unpadded_bytes_per_row = width * 4;
+    let padded_bytes_per_row =
+        unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    receiver.recv()??;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    for row in slice
+        .get_mapped_range()
+        .chunks_exact(padded_bytes_per_row as usize)
+    {
+        pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+    }
+    buffer.unmap();
+
+    if swap_red_blue {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow::anyhow!("Readback buffer doesn't match the texture size"))
+}
+
+/// Copies a texture onto another of the same size and format by drawing it,
+/// for targets that can't be copied into, like most surface textures.
+pub struct Blit {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Blit {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("blit_bind_group_layout"),
+        });
+
+        let shader = fullscreen::create_shader(device, "Blit Shader", include_str!("blit.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = fullscreen::create_pipeline(
+            device,
+            "Blit Pipeline",
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        );
+
+        Self {
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Records drawing `source` over all of `target`. `source` needs
+    /// `TEXTURE_BINDING` usage.
+    pub fn copy(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            }],
+            label: Some("blit_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Saves `image` as a timestamped PNG under `screenshots/`, encoding it on
+/// another thread so the frame isn't held up.
+pub fn save_screenshot(image: image::RgbaImage) {
+    let path = std::path::Path::new("screenshots").join(format!("{}.png", timestamp()));
+    std::thread::spawn(move || {
+        let result = std::fs::create_dir_all("screenshots")
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(image.save(&path)?));
+        match result {
+            Ok(()) => log::info!("Saved screenshot {:?}", path),
+            Err(e) => log::error!("Unable to save screenshot {:?}: {}", path, e),
+        }
+    });
+}
+
+/// The current UTC time as `2024-05-17_14-03-59.123`, which sorts by time and
+/// is safe in file names.
+fn timestamp() -> String {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format_timestamp(since_epoch)
+}
+
+/// `since_epoch` as a UTC date and time that sorts by name.
+fn format_timestamp(since_epoch: std::time::Duration) -> String {
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the date `days` after 1970-01-01, from Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn civil_from_days_starts_at_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn civil_from_days_crosses_years_and_leap_days() {
+        assert_eq!(civil_from_days(10956), (1999, 12, 31));
+        assert_eq!(civil_from_days(10957), (2000, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        // 2100 is not a leap year.
+        assert_eq!(civil_from_days(47540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn timestamps_are_padded_utc() {
+        assert_eq!(format_timestamp(Duration::ZERO), "1970-01-01_00-00-00.000");
+        // A millisecond before midnight on a leap day.
+        let leap_day = Duration::from_secs(11016 * 86400 + 86399) + Duration::from_millis(999);
+        assert_eq!(format_timestamp(leap_day), "2000-02-29_23-59-59.999");
+    }
+}
@@ -0,0 +1,223 @@
+use cgmath::{InnerSpace, SquareMatrix, Transform};
+
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> cgmath::Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    fn transform(&self, matrix: cgmath::Matrix4<f32>) -> Ray {
+        Ray {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Aabb {
+    min: cgmath::Point3<f32>,
+    max: cgmath::Point3<f32>,
+}
+
+impl Aabb {
+    fn grow(&mut self, point: cgmath::Point3<f32>) {
+        self.min = cgmath::Point3::new(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = cgmath::Point3::new(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    /// Whether `ray` passes through the box before `max_distance`.
+    fn hit(&self, ray: &Ray, max_distance: f32) -> bool {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            near = near.max(t0);
+            far = far.min(t1);
+            if near > far {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// CPU copy of a model's triangles, in model space, for ray queries.
+#[derive(Default)]
+pub struct CollisionMesh {
+    triangles: Vec<[cgmath::Point3<f32>; 3]>,
+    bounds: Option<Aabb>,
+}
+
+impl CollisionMesh {
+    /// Adds the triangles of an indexed mesh, with `position` looking up each
+    /// vertex.
+    pub fn extend(&mut self, indices: &[u32], position: impl Fn(usize) -> [f32; 3]) {
+        for triangle in indices.chunks_exact(3) {
+            let corners = [
+                cgmath::Point3::from(position(triangle[0] as usize)),
+                cgmath::Point3::from(position(triangle[1] as usize)),
+                cgmath::Point3::from(position(triangle[2] as usize)),
+            ];
+            for corner in corners {
+                match &mut self.bounds {
+                    Some(bounds) => bounds.grow(corner),
+                    None => {
+                        self.bounds = Some(Aabb {
+                            min: corner,
+                            max: corner,
+                        })
+                    }
+                }
+            }
+            self.triangles.push(corners);
+        }
+    }
+
+    /// Distance along `ray` to the nearest front-facing triangle closer than
+    /// `max_distance`. Back faces are ignored so rays starting inside a closed
+    /// mesh pass out of it.
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        let bounds = self.bounds?;
+        if !bounds.hit(ray, max_distance) {
+            return None;
+        }
+
+        let mut nearest = None;
+        let mut limit = max_distance;
+        for [a, b, c] in &self.triangles {
+            // Möller–Trumbore.
+            let edge1 = b - a;
+            let edge2 = c - a;
+            let p = ray.direction.cross(edge2);
+            let determinant = edge1.dot(p);
+            if determinant <= 0.0 {
+                continue;
+            }
+            let to_origin = ray.origin - a;
+            let u = to_origin.dot(p) / determinant;
+            if !(0.0..=1.0).contains(&u) {
+                continue;
+            }
+            let q = to_origin.cross(edge1);
+            let v = ray.direction.dot(q) / determinant;
+            if v < 0.0 || u + v > 1.0 {
+                continue;
+            }
+            let distance = edge2.dot(q) / determinant;
+            if distance >= 0.0 && distance < limit {
+                limit = distance;
+                nearest = Some(distance);
+            }
+        }
+        nearest
+    }
+
+    /// Like `raycast`, for an instance of the mesh placed by `world`. The ray
+    /// is moved into model space unnormalized, so distances stay in world
+    /// units.
+    pub fn raycast_instance(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        world: cgmath::Matrix4<f32>,
+    ) -> Option<f32> {
+        let inverse = world.invert()?;
+        self.raycast(&ray.transform(inverse), max_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Rotation3;
+
+    /// A square from -1 to 1 across X and Y at height `z`, facing +Z.
+    fn square(mesh: &mut CollisionMesh, z: f32) {
+        let corners = [
+            [-1.0, -1.0, z],
+            [1.0, -1.0, z],
+            [1.0, 1.0, z],
+            [-1.0, 1.0, z],
+        ];
+        mesh.extend(&[0, 1, 2, 0, 2, 3], |index| corners[index]);
+    }
+
+    fn down_from(x: f32, y: f32, z: f32) -> Ray {
+        Ray {
+            origin: cgmath::Point3::new(x, y, z),
+            direction: -cgmath::Vector3::unit_z(),
+        }
+    }
+
+    #[test]
+    fn rays_stop_at_the_nearest_triangle() {
+        let mut mesh = CollisionMesh::default();
+        square(&mut mesh, 0.0);
+        square(&mut mesh, 2.0);
+        assert_eq!(mesh.raycast(&down_from(0.5, 0.25, 5.0), 100.0), Some(3.0));
+    }
+
+    #[test]
+    fn rays_miss_beside_behind_and_through_the_back() {
+        let mut mesh = CollisionMesh::default();
+        square(&mut mesh, 0.0);
+        assert_eq!(mesh.raycast(&down_from(1.5, 0.0, 5.0), 100.0), None);
+        assert_eq!(mesh.raycast(&down_from(0.0, 0.0, -1.0), 100.0), None);
+        let up = Ray {
+            origin: cgmath::Point3::new(0.0, 0.0, -1.0),
+            direction: cgmath::Vector3::unit_z(),
+        };
+        assert_eq!(mesh.raycast(&up, 100.0), None);
+        assert_eq!(CollisionMesh::default().raycast(&up, 100.0), None);
+    }
+
+    #[test]
+    fn rays_ignore_triangles_past_max_distance() {
+        let mut mesh = CollisionMesh::default();
+        square(&mut mesh, 0.0);
+        assert_eq!(mesh.raycast(&down_from(0.0, 0.0, 5.0), 4.9), None);
+        assert_eq!(mesh.raycast(&down_from(0.0, 0.0, 5.0), 5.1), Some(5.0));
+    }
+
+    #[test]
+    fn instances_are_hit_where_they_are_placed_in_world_units() {
+        let mut mesh = CollisionMesh::default();
+        square(&mut mesh, 0.0);
+        // Doubled in size, stood up to face +X and moved to x = 3.
+        let world = cgmath::Matrix4::from_translation(cgmath::Vector3::new(3.0, 0.0, 0.0))
+            * cgmath::Matrix4::from(cgmath::Quaternion::from_angle_y(cgmath::Deg(90.0)))
+            * cgmath::Matrix4::from_scale(2.0);
+        let ray = Ray {
+            origin: cgmath::Point3::new(10.0, 1.5, -1.5),
+            direction: -cgmath::Vector3::unit_x(),
+        };
+
+        let distance = mesh.raycast_instance(&ray, 100.0, world).unwrap();
+        assert!((distance - 7.0).abs() < 1e-5, "hit at {}", distance);
+        assert_eq!(mesh.raycast_instance(&ray, 6.9, world), None);
+        // Past the edge of the scaled square.
+        let beside = Ray {
+            origin: cgmath::Point3::new(10.0, 2.5, 0.0),
+            ..ray
+        };
+        assert_eq!(mesh.raycast_instance(&beside, 100.0, world), None);
+    }
+}
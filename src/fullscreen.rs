@@ -0,0 +1,46 @@
+//! The triangle that post-processing passes draw to run a fragment shader
+//! over every pixel of their target.
+
+/// Compiles `source` after `fullscreen.wgsl`, which gives it `VertexOutput`
+/// and the `vs_main` entry point.
+pub fn create_shader(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(
+            format!("{}{}", include_str!("fullscreen.wgsl"), source).into(),
+        ),
+    })
+}
+
+/// A pipeline drawing the fullscreen triangle into `target` with
+/// `fragment_entry_point`, from a shader made by `create_shader`.
+pub fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    target: wgpu::ColorTargetState,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(fragment_entry_point),
+            targets: &[Some(target)],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview_mask: None,
+        cache: None,
+    })
+}
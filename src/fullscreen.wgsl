@@ -0,0 +1,19 @@
+// One triangle big enough to cover the screen, with no vertex buffer, for
+// passes that run a fragment shader over every pixel. `fullscreen.rs` puts
+// this ahead of each of their shaders.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 to 1 across the target, from the top left.
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32((vertex_index << 1u) & 2u);
+    let y = f32(vertex_index & 2u);
+    var out: VertexOutput;
+    out.uv = vec2<f32>(x, y);
+    out.clip_position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    return out;
+}
@@ -0,0 +1,132 @@
+use cgmath::Rotation3;
+
+use crate::input::Input;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+/// Splits variable frame times into fixed simulation ticks. Whatever time is
+/// left over carries into the next frame, and `alpha` says how far the frame
+/// is between the last tick and the next.
+pub struct FixedTimestep {
+    tick: f32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    /// Frame time beyond this is dropped, so after a stall the simulation
+    /// slows down for a frame instead of running hundreds of ticks to catch
+    /// up.
+    const MAX_FRAME_TIME: f32 = 0.25;
+
+    pub fn new(ticks_per_second: f32) -> Self {
+        Self {
+            tick: 1.0 / ticks_per_second,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Seconds per tick.
+    pub fn tick(&self) -> f32 {
+        self.tick
+    }
+
+    /// Adds `frame_time` seconds and returns how many ticks are due.
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time.min(Self::MAX_FRAME_TIME);
+        let ticks = (self.accumulator / self.tick).floor();
+        self.accumulator -= ticks * self.tick;
+        ticks as u32
+    }
+
+    /// Fraction of a tick since the last one, for blending the previous and
+    /// current simulation states.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick).clamp(0.0, 1.0)
+    }
+}
+
+/// What a `TickSystem` can read and change on each tick.
+pub struct TickContext<'a> {
+    /// Moves made with `set_local` are blended across the tick when drawn.
+    pub scene_graph: &'a mut SceneGraph,
+    #[allow(unused)]
+    pub input: &'a Input,
+    /// Seconds per tick; always the same.
+    pub delta: f32,
+}
+
+/// Game logic that runs once per simulation tick, independent of frame rate.
+/// Add one with `State::add_system`.
+pub trait TickSystem {
+    fn tick(&mut self, context: &mut TickContext);
+}
+
+/// Turns nodes about their vertical axis at a steady rate, for instances
+/// given a `spin` in the scene file.
+pub struct Spin {
+    /// Each node with its speed in degrees per second.
+    pub nodes: Vec<(NodeId, f32)>,
+}
+
+impl TickSystem for Spin {
+    fn tick(&mut self, context: &mut TickContext) {
+        for &(node, speed) in &self.nodes {
+            let mut local = *context.scene_graph.local(node);
+            let turn = cgmath::Quaternion::from_angle_y(cgmath::Deg(speed * context.delta));
+            local.rotation = turn * local.rotation;
+            context.scene_graph.set_local(node, local);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::Transform;
+    use crate::input::InputMap;
+    use cgmath::{InnerSpace, One};
+
+    #[test]
+    fn leftover_time_carries_into_the_next_frame() {
+        let mut timestep = FixedTimestep::new(8.0);
+        assert_eq!(timestep.advance(0.1875), 1);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.0625), 1);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(0.0625), 0);
+        assert_eq!(timestep.alpha(), 0.5);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut timestep = FixedTimestep::new(8.0);
+        assert_eq!(timestep.advance(10.0), 2);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    fn spin_turns_by_its_speed_each_tick() {
+        let mut graph = SceneGraph::new();
+        let rest = Transform {
+            translation: cgmath::Vector3::new(1.0, 2.0, 3.0),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        };
+        let node = graph.add(None, None, rest, None);
+        let mut spin = Spin {
+            nodes: vec![(node, 90.0)],
+        };
+        let input = Input::new(InputMap::default());
+        let mut context = TickContext {
+            scene_graph: &mut graph,
+            input: &input,
+            delta: 0.5,
+        };
+        spin.tick(&mut context);
+        spin.tick(&mut context);
+
+        let local = graph.local(node);
+        assert_eq!(local.translation, rest.translation);
+        let expected = cgmath::Quaternion::from_angle_y(cgmath::Deg(90.0));
+        assert!((local.rotation - expected).magnitude() < 1e-5);
+    }
+}
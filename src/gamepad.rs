@@ -0,0 +1,267 @@
+#[cfg(gilrs)]
+use crate::input::{GamepadAxis, GamepadButton};
+use crate::input::{GamepadEvent, GamepadId};
+
+/// A source of gamepad events, drained once a frame by `State::update`.
+pub trait GamepadBackend {
+    fn next_event(&mut self) -> Option<(GamepadId, GamepadEvent)>;
+}
+
+/// The platform's gamepads, or `None` if they can't be opened or gilrs isn't
+/// built in, as on Linux without the `gamepad` feature.
+pub fn default_backend() -> Option<Box<dyn GamepadBackend>> {
+    #[cfg(gilrs)]
+    match GilrsBackend::new() {
+        Ok(backend) => return Some(Box::new(backend)),
+        Err(e) => log::warn!("Gamepads unavailable: {}", e),
+    }
+    None
+}
+
+#[cfg(gilrs)]
+pub struct GilrsBackend {
+    gilrs: gilrs::Gilrs,
+}
+
+#[cfg(gilrs)]
+impl GilrsBackend {
+    pub fn new() -> anyhow::Result<Self> {
+        let gilrs = gilrs::Gilrs::new().map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(Self { gilrs })
+    }
+}
+
+#[cfg(gilrs)]
+impl GamepadBackend for GilrsBackend {
+    fn next_event(&mut self) -> Option<(GamepadId, GamepadEvent)> {
+        use gilrs::EventType;
+
+        // Skip events with no equivalent, like the guide button or a
+        // gamepad connecting.
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let event = match event {
+                EventType::ButtonPressed(button, _) => {
+                    convert_button(button).map(GamepadEvent::ButtonPressed)
+                }
+                EventType::ButtonReleased(button, _) => {
+                    convert_button(button).map(GamepadEvent::ButtonReleased)
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    convert_axis(axis).map(|axis| GamepadEvent::AxisChanged(axis, value))
+                }
+                EventType::Disconnected => Some(GamepadEvent::Disconnected),
+                _ => None,
+            };
+            if let Some(event) = event {
+                return Some((GamepadId(id.into()), event));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(gilrs)]
+fn convert_button(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button;
+
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::West => GamepadButton::West,
+        Button::North => GamepadButton::North,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+#[cfg(gilrs)]
+fn convert_axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+    use gilrs::Axis;
+
+    Some(match axis {
+        Axis::LeftStickX => GamepadAxis::LeftStickX,
+        Axis::LeftStickY => GamepadAxis::LeftStickY,
+        Axis::RightStickX => GamepadAxis::RightStickX,
+        Axis::RightStickY => GamepadAxis::RightStickY,
+        _ => return None,
+    })
+}
+
+/// Replays scripted events, for driving input without hardware.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockGamepad {
+    events: std::collections::VecDeque<(GamepadId, GamepadEvent)>,
+}
+
+#[cfg(test)]
+impl MockGamepad {
+    pub fn push(&mut self, pad: usize, event: GamepadEvent) {
+        self.events.push_back((GamepadId(pad), event));
+    }
+}
+
+#[cfg(test)]
+impl GamepadBackend for MockGamepad {
+    fn next_event(&mut self) -> Option<(GamepadId, GamepadEvent)> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, CameraController, OrbitController, Projection};
+    use crate::input::{Action, Axis, GamepadAxis, GamepadButton, Input, InputMap};
+    use crate::texture::DepthMode;
+
+    fn input() -> Input {
+        let map: InputMap = ron::from_str(
+            "(
+                actions: {
+                    toggle_camera_mode: [Gamepad(North)],
+                    orbit_rotate: [Gamepad(LeftBumper)],
+                },
+                axes: {
+                    move_y: [Gamepad(axis: LeftStickY)],
+                    look_x: [Gamepad(axis: RightStickX, scale: 10.0)],
+                },
+            )",
+        )
+        .unwrap();
+        Input::new(map)
+    }
+
+    fn poll(input: &mut Input, gamepad: &mut MockGamepad) -> Vec<Action> {
+        std::iter::from_fn(|| gamepad.next_event())
+            .flat_map(|(pad, event)| input.handle_gamepad(pad, event))
+            .collect()
+    }
+
+    #[test]
+    fn deadzone_and_curve() {
+        let mut input = input();
+        let mut gamepad = MockGamepad::default();
+
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, 0.1));
+        poll(&mut input, &mut gamepad);
+        assert_eq!(input.axis(Axis::MoveY), 0.0);
+
+        // Halfway between the default 0.15 deadzone and the edge, squared.
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, 0.575));
+        poll(&mut input, &mut gamepad);
+        assert!((input.axis(Axis::MoveY) - 0.25).abs() < 1e-5);
+
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, -1.0));
+        poll(&mut input, &mut gamepad);
+        assert!((input.axis(Axis::MoveY) + 1.0).abs() < 1e-5);
+
+        gamepad.push(0, GamepadEvent::Disconnected);
+        poll(&mut input, &mut gamepad);
+        assert_eq!(input.axis(Axis::MoveY), 0.0);
+    }
+
+    #[test]
+    fn buttons_start_actions() {
+        let mut input = input();
+        let mut gamepad = MockGamepad::default();
+
+        gamepad.push(0, GamepadEvent::ButtonPressed(GamepadButton::North));
+        gamepad.push(0, GamepadEvent::ButtonPressed(GamepadButton::South));
+        assert_eq!(
+            poll(&mut input, &mut gamepad),
+            vec![Action::ToggleCameraMode]
+        );
+        assert!(input.is_pressed(Action::ToggleCameraMode));
+
+        gamepad.push(0, GamepadEvent::ButtonReleased(GamepadButton::North));
+        poll(&mut input, &mut gamepad);
+        assert!(!input.is_pressed(Action::ToggleCameraMode));
+    }
+
+    #[test]
+    fn each_gamepad_keeps_its_own_state() {
+        let mut input = input();
+        let mut gamepad = MockGamepad::default();
+        gamepad.push(0, GamepadEvent::ButtonPressed(GamepadButton::North));
+        gamepad.push(1, GamepadEvent::ButtonPressed(GamepadButton::North));
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, 1.0));
+        gamepad.push(
+            1,
+            GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, -0.575),
+        );
+        assert_eq!(
+            poll(&mut input, &mut gamepad),
+            vec![Action::ToggleCameraMode]
+        );
+        assert!((input.axis(Axis::MoveY) - 1.0).abs() < 1e-5);
+
+        // The other gamepad still holds the button and the stick.
+        gamepad.push(0, GamepadEvent::Disconnected);
+        poll(&mut input, &mut gamepad);
+        assert!(input.is_pressed(Action::ToggleCameraMode));
+        assert!((input.axis(Axis::MoveY) + 0.25).abs() < 1e-5);
+
+        gamepad.push(1, GamepadEvent::ButtonReleased(GamepadButton::North));
+        poll(&mut input, &mut gamepad);
+        assert!(!input.is_pressed(Action::ToggleCameraMode));
+    }
+
+    fn camera() -> Camera {
+        let projection = Projection::Perspective {
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        Camera::looking_at(
+            (0.0, 0.0, 0.0).into(),
+            (1.0, 0.0, 0.0).into(),
+            projection,
+            DepthMode::Standard,
+        )
+    }
+
+    #[test]
+    fn sticks_drive_the_camera() {
+        let mut input = input();
+        let mut gamepad = MockGamepad::default();
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::LeftStickY, 1.0));
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::RightStickX, 1.0));
+        poll(&mut input, &mut gamepad);
+
+        let mut camera = camera();
+        CameraController::new(2.0, 0.1).update_camera(&mut camera, &input, 0.5);
+
+        assert!((camera.position.x - 1.0).abs() < 1e-5);
+        assert!((camera.yaw.0 - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sticks_orbit_by_the_tick_length() {
+        let mut input = input();
+        let mut gamepad = MockGamepad::default();
+        gamepad.push(0, GamepadEvent::ButtonPressed(GamepadButton::LeftBumper));
+        gamepad.push(0, GamepadEvent::AxisChanged(GamepadAxis::RightStickX, 1.0));
+        poll(&mut input, &mut gamepad);
+
+        // Ten pixels a sixtieth of a second, at a hundredth of a radian each.
+        let mut camera = camera();
+        let mut orbit = OrbitController::new(5.0, 0.01, 0.1);
+        orbit.update_camera(&mut camera, &input, 1.0 / 60.0);
+        assert!((camera.yaw.0 - 0.1).abs() < 1e-5);
+        orbit.update_camera(&mut camera, &input, 1.0 / 30.0);
+        assert!((camera.yaw.0 - 0.3).abs() < 1e-5);
+    }
+}
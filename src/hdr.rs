@@ -0,0 +1,343 @@
+use serde::Deserialize;
+
+use crate::bloom::BloomOutput;
+use crate::fullscreen;
+use crate::texture::Texture;
+
+/// Curve that brings HDR color into the displayable range.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Tonemapper {
+    /// Filmic, with a slight warm shift in the highlights.
+    #[default]
+    Aces,
+    /// Plain `x / (1 + x)`; soft, and keeps hues but washes them out.
+    Reinhard,
+    /// Desaturates towards white as things get brighter, like film.
+    AgX,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::AgX,
+            Tonemapper::AgX => Tonemapper::Aces,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Tonemapping {
+    pub tonemapper: Tonemapper,
+    /// In stops: each one doubles the brightness going into the curve.
+    pub exposure: f32,
+}
+
+impl Default for Tonemapping {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::default(),
+            exposure: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemappingUniform {
+    exposure: f32,
+    tonemapper: u32,
+    encode_srgb: u32,
+    bloom_intensity: f32,
+    bloom_scale: f32,
+    _padding: [u32; 3],
+}
+
+/// The color target the scene is drawn into, and the pass that tonemaps it
+/// onto the surface with any bloom blended in.
+pub struct HdrPipeline {
+    texture: Texture,
+    format: wgpu::TextureFormat,
+    pub tonemapping: Tonemapping,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    bloom_layout: wgpu::BindGroupLayout,
+    /// Bound in place of the bloom while there isn't one.
+    no_bloom: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    encode_srgb: bool,
+}
+
+impl HdrPipeline {
+    /// `output_format` is what `process` writes to, normally the surface's.
+    /// Uses `Rgba16Float` for the color target where it can be rendered to,
+    /// and otherwise falls back to 8 bits, which clips like drawing straight
+    /// to the surface did.
+    pub fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::Adapter,
+        width: u32,
+        height: u32,
+        output_format: wgpu::TextureFormat,
+        tonemapping: Tonemapping,
+    ) -> Self {
+        let format = if adapter
+            .get_texture_format_features(wgpu::TextureFormat::Rgba16Float)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        {
+            wgpu::TextureFormat::Rgba16Float
+        } else {
+            log::warn!("Rgba16Float can't be rendered to; HDR is clipped to 8 bits");
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        };
+        let texture = create_color_target(device, width, height, format);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemapping Buffer"),
+            size: std::mem::size_of::<TonemappingUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("hdr_bind_group_layout"),
+        });
+        let bind_group = create_bind_group(device, &bind_group_layout, &texture, &uniform_buffer);
+
+        let bloom_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("bloom_output_bind_group_layout"),
+        });
+        // Textures start out zeroed, so this one is black.
+        let black = create_color_target(device, 1, 1, format);
+        let no_bloom = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bloom_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&black.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&black.sampler),
+                },
+            ],
+            label: Some("no_bloom_bind_group"),
+        });
+
+        let shader =
+            fullscreen::create_shader(device, "Tonemapping Shader", include_str!("hdr.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemapping Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &bloom_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = fullscreen::create_pipeline(
+            device,
+            "Tonemapping Pipeline",
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            wgpu::ColorTargetState {
+                format: output_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        );
+
+        Self {
+            texture,
+            format,
+            tonemapping,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            bloom_layout,
+            no_bloom,
+            pipeline,
+            encode_srgb: !output_format.is_srgb(),
+        }
+    }
+
+    /// Format of the color target, for pipelines that draw into it.
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    /// Layout of `BloomOutput::bind_group`: the bloom texture and a sampler.
+    pub fn bloom_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bloom_layout
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.texture = create_color_target(device, width, height, self.format);
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.texture,
+            &self.uniform_buffer,
+        );
+    }
+
+    /// Uploads `tonemapping` and how strongly to blend `bloom`, for the next
+    /// `process`.
+    pub fn update(&self, queue: &wgpu::Queue, bloom: Option<&BloomOutput>) {
+        let uniform = TonemappingUniform {
+            exposure: self.tonemapping.exposure.exp2(),
+            tonemapper: self.tonemapping.tonemapper as u32,
+            encode_srgb: self.encode_srgb as u32,
+            bloom_intensity: bloom.map_or(0.0, |bloom| bloom.intensity),
+            bloom_scale: bloom.map_or(0.0, |bloom| 1.0 / bloom.levels as f32),
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Tonemaps the color target into `output`, which must be in the format
+    /// given to `new`, blending in `bloom` if there is one.
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        bloom: Option<&BloomOutput>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemapping Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        let bloom = bloom.map_or(&self.no_bloom, |bloom| bloom.bind_group);
+        pass.set_bind_group(1, bloom, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_color_target(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("hdr_texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // Sampled one texel per pixel, so filtering never kicks in.
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &Texture,
+    uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("hdr_bind_group"),
+    })
+}
@@ -0,0 +1,115 @@
+// Tonemaps the HDR color target into the surface's displayable range.
+
+struct Tonemapping {
+    // Linear factor, already raised from stops.
+    exposure: f32,
+    // 0 = ACES, 1 = Reinhard, 2 = AgX. Matches `Tonemapper`.
+    tonemapper: u32,
+    // Set when the output format isn't sRGB, so the shader has to encode.
+    encode_srgb: u32,
+    // Share of the final image that's bloom, before tonemapping.
+    bloom_intensity: f32,
+    // Averages the bloom levels summed into `bloom_texture`.
+    bloom_scale: f32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+}
+
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var hdr_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> tonemapping: Tonemapping;
+
+@group(1) @binding(0)
+var bloom_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var bloom_sampler: sampler;
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms.
+// Matrices are written row by row, so vectors multiply from the left.
+const ACES_INPUT = mat3x3<f32>(
+    0.59719, 0.35458, 0.04823,
+    0.07600, 0.90834, 0.01566,
+    0.02840, 0.13383, 0.83777,
+);
+const ACES_OUTPUT = mat3x3<f32>(
+    1.60475, -0.53108, -0.07367,
+    -0.10208, 1.10813, -0.00605,
+    -0.00327, -0.07276, 1.07602,
+);
+
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let v = color * ACES_INPUT;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp((a / b) * ACES_OUTPUT, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// AgX with the polynomial contrast curve from Benjamin Wrensch's minimal
+// version of Troy Sobotka's transform. These matrices are column by column.
+const AGX_INSET = mat3x3<f32>(
+    0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+    0.0784335999999992, 0.878468636469772, 0.0784336,
+    0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+);
+const AGX_OUTSET = mat3x3<f32>(
+    1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+    -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+    -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+);
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    var v = AGX_INSET * max(color, vec3<f32>(1e-10));
+    v = clamp(log2(v), vec3<f32>(AGX_MIN_EV), vec3<f32>(AGX_MAX_EV));
+    v = (v - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    v = AGX_OUTSET * agx_contrast(v);
+    // The curve's output is display encoded; bring it back to linear.
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn encode_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let bloom = textureSample(bloom_texture, bloom_sampler, in.uv).rgb * tonemapping.bloom_scale;
+    var hdr = textureSample(hdr_texture, hdr_sampler, in.uv).rgb;
+    hdr = mix(hdr, bloom, tonemapping.bloom_intensity) * tonemapping.exposure;
+
+    var color: vec3<f32>;
+    switch tonemapping.tonemapper {
+        case 1u: {
+            color = reinhard(hdr);
+        }
+        case 2u: {
+            color = agx(hdr);
+        }
+        default: {
+            color = aces(hdr);
+        }
+    }
+
+    if tonemapping.encode_srgb != 0u {
+        color = encode_srgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
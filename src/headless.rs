@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use crate::recording::{InputLog, Recording};
+use crate::state::State;
+
+/// Renders scenes without a window, for thumbnails and checking the look of a
+/// scene in CI. Uses a software adapter when there's no GPU.
+pub struct HeadlessRenderer {
+    state: State,
+}
+
+impl HeadlessRenderer {
+    /// Loads `scene_path`, relative to `res/`, to render at the given size.
+    pub async fn new(scene_path: &str, width: u32, height: u32) -> anyhow::Result<Self> {
+        let state = State::new_headless(width, height, scene_path, InputLog::Live).await?;
+        Ok(Self { state })
+    }
+
+    /// Loads the scene `recording` was made in and plays its input back as
+    /// the simulation runs.
+    pub async fn replay(recording: &Path, width: u32, height: u32) -> anyhow::Result<Self> {
+        let recording = Recording::load(recording)?;
+        let scene = recording.scene.clone();
+        let state = State::new_headless(width, height, &scene, InputLog::replay(recording)).await?;
+        Ok(Self { state })
+    }
+
+    /// Runs the simulation on by `seconds`, as if a frame took that long.
+    pub fn advance(&mut self, seconds: f32) {
+        self.state.advance(seconds);
+    }
+
+    /// Moves the camera to `eye`, facing `target`, for a fixed view.
+    pub fn look_at(&mut self, eye: [f32; 3], target: [f32; 3]) {
+        self.state.place_camera(eye.into(), target.into());
+    }
+
+    /// Whether a replay still has input left to play.
+    pub fn replaying(&self) -> bool {
+        self.state.replaying()
+    }
+
+    pub fn capture(&mut self) -> anyhow::Result<image::RgbaImage> {
+        // Uploads uniforms without moving the simulation on, in case nothing
+        // has been advanced yet.
+        self.state.advance(0.0);
+        self.state.capture()
+    }
+
+    pub fn save_png(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.capture()?.save(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use winit::event::{MouseButton, MouseScrollDelta};
+use winit::keyboard::KeyCode;
+
+use crate::resources;
+
+/// Bindings loaded at startup, relative to `res/`.
+pub const DEFAULT_INPUT_MAP: &str = "input.ron";
+
+/// The frame length that stick and action axis values are per when read as
+/// movement with `Input::axis_movement`.
+const MOVEMENT_FRAME: f32 = 1.0 / 60.0;
+
+/// Something the player can do, independent of which input does it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    /// Held to turn the view in orbit mode.
+    OrbitRotate,
+    /// Held to move the focus point in orbit mode.
+    OrbitPan,
+    ToggleCameraMode,
+    ToggleProjection,
+    /// Drops a copy of the scene's first model in front of the camera.
+    PlaceProp,
+    /// Turns the newest prop about the vertical axis.
+    TurnProp,
+    RemoveProp,
+    /// Moves whatever the next input pressed does onto the input pressed
+    /// after it, until the game closes. Axes other than those made of
+    /// actions are only set in the file.
+    Rebind,
+    /// Switches to the next tonemapping curve.
+    CycleTonemapper,
+    ExposureUp,
+    ExposureDown,
+    ToggleBloom,
+    BloomIntensityUp,
+    BloomIntensityDown,
+    /// Raises the brightness below which nothing glows.
+    BloomThresholdUp,
+    BloomThresholdDown,
+    /// Widens the glow around bright pixels.
+    BloomRadiusUp,
+    BloomRadiusDown,
+    /// Saves the next frame to `screenshots/`.
+    Screenshot,
+    /// Releases the cursor if it's grabbed, and otherwise quits.
+    Quit,
+}
+
+/// A continuous value made up of one or more `AxisBinding`s.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Axis {
+    MoveX,
+    MoveY,
+    LookX,
+    LookY,
+    Zoom,
+}
+
+/// Gamepad buttons by position, so bindings read the same across layouts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    /// Positive is up.
+    LeftStickY,
+    RightStickX,
+    /// Positive is up.
+    RightStickY,
+}
+
+impl GamepadAxis {
+    /// Index into `Input::sticks`, and whether this is the stick's Y axis.
+    fn stick(self) -> (usize, bool) {
+        match self {
+            GamepadAxis::LeftStickX => (0, false),
+            GamepadAxis::LeftStickY => (0, true),
+            GamepadAxis::RightStickX => (1, false),
+            GamepadAxis::RightStickY => (1, true),
+        }
+    }
+}
+
+/// Which gamepad an event came from, as numbered by the backend.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GamepadId(pub usize);
+
+/// What a `GamepadBackend` reports. Every connected gamepad drives the same
+/// actions: a button counts as held while any gamepad holds it, and each
+/// stick follows whichever gamepad pushes it furthest.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GamepadEvent {
+    ButtonPressed(GamepadButton),
+    ButtonReleased(GamepadButton),
+    /// Raw stick position from -1 to 1.
+    AxisChanged(GamepadAxis, f32),
+    Disconnected,
+}
+
+/// One input event as `State` receives it. Everything that reaches the
+/// simulation comes through one of these, so a session can be recorded and
+/// replayed.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key {
+        code: KeyCode,
+        pressed: bool,
+    },
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    /// Cursor position in pixels.
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    /// Raw mouse movement while the cursor is grabbed.
+    MouseMotion {
+        delta_x: f64,
+        delta_y: f64,
+    },
+    Scroll(MouseScrollDelta),
+    Gamepad(GamepadId, GamepadEvent),
+}
+
+/// How stick positions turn into axis values.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct StickResponse {
+    /// Distance from the centre, from 0 to 1, that's treated as no input so
+    /// worn sticks don't drift.
+    #[serde(default = "default_deadzone")]
+    pub deadzone: f32,
+    /// Power applied to the distance past the deadzone. Above 1 gives finer
+    /// control near the centre.
+    #[serde(default = "default_exponent")]
+    pub exponent: f32,
+}
+
+impl Default for StickResponse {
+    fn default() -> Self {
+        Self {
+            deadzone: default_deadzone(),
+            exponent: default_exponent(),
+        }
+    }
+}
+
+fn default_deadzone() -> f32 {
+    0.15
+}
+
+fn default_exponent() -> f32 {
+    2.0
+}
+
+impl StickResponse {
+    /// Applies a radial deadzone and the response curve, keeping the stick's
+    /// direction.
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let distance = (x * x + y * y).sqrt();
+        if distance <= self.deadzone {
+            return (0.0, 0.0);
+        }
+        let past_deadzone = ((distance - self.deadzone) / (1.0 - self.deadzone)).min(1.0);
+        let scale = past_deadzone.powf(self.exponent) / distance;
+        (x * scale, y * scale)
+    }
+}
+
+/// A physical input that can drive an `Action`. Keys are physical positions,
+/// so `KeyW` is the key labelled Z on an AZERTY keyboard.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum AxisBinding {
+    /// -1 while `negative` is held, 1 while `positive` is held.
+    Actions {
+        negative: Action,
+        positive: Action,
+    },
+    /// Mouse movement in pixels since the last tick.
+    MouseX,
+    MouseY,
+    /// Scroll wheel movement in lines since the last tick, positive away
+    /// from the user.
+    MouseWheel,
+    /// Stick position from -1 to 1 after the `StickResponse`, times `scale`.
+    Gamepad {
+        axis: GamepadAxis,
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Which inputs drive which actions and axes, as loaded from a RON file under
+/// `res/`. The default is `res/input.ron` as it was at build time.
+#[derive(Clone, Debug, Deserialize)]
+pub struct InputMap {
+    #[serde(default)]
+    actions: HashMap<Action, Vec<Binding>>,
+    #[serde(default)]
+    axes: HashMap<Axis, Vec<AxisBinding>>,
+    #[serde(default)]
+    sticks: StickResponse,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        ron::from_str(include_str!("../res/input.ron")).expect("Built-in input map is invalid")
+    }
+}
+
+impl InputMap {
+    pub async fn load(file_name: &str) -> anyhow::Result<InputMap> {
+        let text = resources::load_string(file_name).await?;
+        let map = ron::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse input map {:?}: {}", file_name, e))?;
+        Ok(map)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_bound(&self, action: Action, binding: Binding) -> bool {
+        self.bindings(action).contains(&binding)
+    }
+
+    /// Adds `binding` to `action`, leaving its other bindings in place. An
+    /// input may drive several actions at once.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.actions.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, binding: Binding) {
+        if let Some(bindings) = self.actions.get_mut(&action) {
+            bindings.retain(|bound| *bound != binding);
+        }
+    }
+
+    /// Moves every action bound to `old` onto `new` and returns them. Actions
+    /// already on `new` move to `old` in exchange, so nothing is left
+    /// unbound. This takes effect straight away, including for held inputs.
+    pub fn rebind(&mut self, old: Binding, new: Binding) -> Vec<Action> {
+        let moved = self.actions_bound_to(old).collect::<Vec<_>>();
+        if moved.is_empty() || old == new {
+            return moved;
+        }
+        let displaced = self.actions_bound_to(new).collect::<Vec<_>>();
+        for &action in &moved {
+            self.unbind(action, old);
+        }
+        for &action in &displaced {
+            self.unbind(action, new);
+            self.bind(action, old);
+        }
+        for &action in &moved {
+            self.bind(action, new);
+        }
+        moved
+    }
+
+    pub fn actions_bound_to(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.actions
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+}
+
+/// The current state of every input, read through an `InputMap`. Events are
+/// fed in as they arrive; controllers query actions and axes once a tick,
+/// after which `end_tick` clears the motion they've used.
+pub struct Input {
+    map: InputMap,
+    held: HashSet<Binding>,
+    mouse_delta: (f32, f32),
+    wheel: f32,
+    rebinding: Option<Rebinding>,
+    gamepads: HashMap<GamepadId, GamepadState>,
+}
+
+#[derive(Default)]
+struct GamepadState {
+    buttons: HashSet<GamepadButton>,
+    /// Raw left and right stick positions.
+    sticks: [(f32, f32); 2],
+}
+
+/// How far through `Input::start_rebinding` the player is.
+#[derive(Copy, Clone, Debug)]
+enum Rebinding {
+    /// Waiting for the input to move.
+    From,
+    /// Waiting for the input to move this one's actions onto.
+    To(Binding),
+}
+
+impl Input {
+    pub fn new(map: InputMap) -> Self {
+        Self {
+            map,
+            held: HashSet::new(),
+            mouse_delta: (0.0, 0.0),
+            wheel: 0.0,
+            rebinding: None,
+            gamepads: HashMap::new(),
+        }
+    }
+
+    pub fn map(&self) -> &InputMap {
+        &self.map
+    }
+
+    /// Takes the next two fresh presses as an input and where to move its
+    /// actions to, with `InputMap::rebind`, instead of starting anything.
+    /// The change lasts until the game closes; to keep it, or to move an
+    /// analog axis, edit the bindings file.
+    pub fn start_rebinding(&mut self) {
+        log::info!("Press the input to rebind");
+        self.rebinding = Some(Rebinding::From);
+    }
+
+    /// Whether the next press goes to `start_rebinding` rather than to its
+    /// actions.
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    /// Records a press or release and returns the actions it started. Key
+    /// repeats and presses of an action that's already held start nothing.
+    pub fn handle_binding(&mut self, binding: Binding, is_pressed: bool) -> Vec<Action> {
+        if !is_pressed {
+            self.held.remove(&binding);
+            return Vec::new();
+        }
+        if self.held.contains(&binding) {
+            return Vec::new();
+        }
+        if let Some(step) = self.rebinding.take() {
+            self.held.insert(binding);
+            self.continue_rebinding(step, binding);
+            return Vec::new();
+        }
+        let started = self
+            .map
+            .actions_bound_to(binding)
+            .filter(|action| !self.is_pressed(*action))
+            .collect();
+        self.held.insert(binding);
+        started
+    }
+
+    fn continue_rebinding(&mut self, step: Rebinding, binding: Binding) {
+        match step {
+            Rebinding::From => {
+                let actions = self.map.actions_bound_to(binding).collect::<Vec<_>>();
+                if actions.is_empty() {
+                    log::warn!("{:?} isn't bound to anything", binding);
+                } else {
+                    log::info!("Press the new input for {:?}", actions);
+                    self.rebinding = Some(Rebinding::To(binding));
+                }
+            }
+            Rebinding::To(old) => {
+                let moved = self.map.rebind(old, binding);
+                log::info!(
+                    "Rebound {:?} from {:?} to {:?} until the game closes",
+                    moved,
+                    old,
+                    binding
+                );
+            }
+        }
+    }
+
+    /// Records an event from gamepad `pad` and returns the actions it
+    /// started.
+    pub fn handle_gamepad(&mut self, pad: GamepadId, event: GamepadEvent) -> Vec<Action> {
+        match event {
+            GamepadEvent::ButtonPressed(button) => {
+                self.gamepads.entry(pad).or_default().buttons.insert(button);
+                return self.handle_binding(Binding::Gamepad(button), true);
+            }
+            GamepadEvent::ButtonReleased(button) => {
+                if let Some(state) = self.gamepads.get_mut(&pad) {
+                    state.buttons.remove(&button);
+                }
+                self.release_gamepad_button(button);
+            }
+            GamepadEvent::AxisChanged(axis, value) => {
+                let (stick, is_y) = axis.stick();
+                let stick = &mut self.gamepads.entry(pad).or_default().sticks[stick];
+                if is_y {
+                    stick.1 = value;
+                } else {
+                    stick.0 = value;
+                }
+            }
+            GamepadEvent::Disconnected => {
+                if let Some(state) = self.gamepads.remove(&pad) {
+                    for button in state.buttons {
+                        self.release_gamepad_button(button);
+                    }
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    /// Lets go of `button` unless another gamepad still holds it.
+    fn release_gamepad_button(&mut self, button: GamepadButton) {
+        if !self
+            .gamepads
+            .values()
+            .any(|state| state.buttons.contains(&button))
+        {
+            self.handle_binding(Binding::Gamepad(button), false);
+        }
+    }
+
+    pub fn handle_mouse_moved(&mut self, delta_x: f32, delta_y: f32) {
+        self.mouse_delta.0 += delta_x;
+        self.mouse_delta.1 += delta_y;
+    }
+
+    pub fn handle_scroll(&mut self, lines: f32) {
+        self.wheel += lines;
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|binding| self.held.contains(binding))
+    }
+
+    pub fn axis(&self, axis: Axis) -> f32 {
+        let (movement, rate) = self.axis_parts(axis);
+        movement + rate
+    }
+
+    /// `axis` as a distance moved over a tick of `delta` seconds, for
+    /// controllers that drag by the look axes rather than turn at a speed.
+    /// Mouse and wheel movement is that already; sticks and held actions give
+    /// their value for every `MOVEMENT_FRAME` of `delta`.
+    pub fn axis_movement(&self, axis: Axis, delta: f32) -> f32 {
+        let (movement, rate) = self.axis_parts(axis);
+        movement + rate * delta / MOVEMENT_FRAME
+    }
+
+    /// `axis` split into movement since the last tick, from the mouse and
+    /// wheel, and rates, from everything else.
+    fn axis_parts(&self, axis: Axis) -> (f32, f32) {
+        let Some(bindings) = self.map.axes.get(&axis) else {
+            return (0.0, 0.0);
+        };
+        let mut movement = 0.0;
+        let mut rate = 0.0;
+        for binding in bindings {
+            match *binding {
+                AxisBinding::Actions { negative, positive } => {
+                    rate += self.is_pressed(positive) as i32 as f32
+                        - self.is_pressed(negative) as i32 as f32;
+                }
+                AxisBinding::MouseX => movement += self.mouse_delta.0,
+                AxisBinding::MouseY => movement += self.mouse_delta.1,
+                AxisBinding::MouseWheel => movement += self.wheel,
+                AxisBinding::Gamepad { axis, scale } => {
+                    let (stick, is_y) = axis.stick();
+                    let value = self
+                        .gamepads
+                        .values()
+                        .map(|state| {
+                            let (x, y) = self.map.sticks.apply(state.sticks[stick]);
+                            if is_y { y } else { x }
+                        })
+                        .fold(0.0f32, |furthest, value| {
+                            if value.abs() > furthest.abs() {
+                                value
+                            } else {
+                                furthest
+                            }
+                        });
+                    rate += value * scale;
+                }
+            }
+        }
+        (movement, rate)
+    }
+
+    /// Lets go of every input and forgets any movement, as when a replay
+    /// hands over to live input.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.gamepads.clear();
+        self.end_tick();
+    }
+
+    /// Forgets mouse and wheel movement once a tick has used it. Movement
+    /// over frames with no tick adds up until the next one.
+    pub fn end_tick(&mut self) {
+        self.mouse_delta = (0.0, 0.0);
+        self.wheel = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: Binding = Binding::Key(KeyCode::KeyW);
+    const Z: Binding = Binding::Key(KeyCode::KeyZ);
+    const UP: Binding = Binding::Key(KeyCode::ArrowUp);
+
+    fn map() -> InputMap {
+        ron::from_str(
+            "(actions: {
+                move_forward: [Key(KeyW), Key(ArrowUp)],
+                move_backward: [Key(KeyS)],
+                quit: [Key(KeyZ)],
+            })",
+        )
+        .unwrap()
+    }
+
+    fn sorted(mut actions: Vec<Action>) -> Vec<Action> {
+        actions.sort_by_key(|action| format!("{:?}", action));
+        actions
+    }
+
+    #[test]
+    fn default_map_is_the_shipped_one() {
+        assert!(!InputMap::default().bindings(Action::Rebind).is_empty());
+    }
+
+    #[test]
+    fn bind_adds_without_duplicating() {
+        let mut map = map();
+        map.bind(Action::MoveForward, W);
+        assert_eq!(map.bindings(Action::MoveForward), [W, UP]);
+        map.bind(Action::MoveBackward, UP);
+        assert_eq!(
+            map.bindings(Action::MoveBackward),
+            [Binding::Key(KeyCode::KeyS), UP]
+        );
+    }
+
+    #[test]
+    fn one_input_can_drive_several_actions() {
+        let mut map = map();
+        map.bind(Action::Quit, W);
+        let mut input = Input::new(map);
+        assert_eq!(
+            sorted(input.handle_binding(W, true)),
+            [Action::MoveForward, Action::Quit]
+        );
+    }
+
+    #[test]
+    fn unbind_only_touches_the_given_action() {
+        let mut map = map();
+        map.bind(Action::Quit, W);
+        map.unbind(Action::MoveForward, W);
+        assert_eq!(map.bindings(Action::MoveForward), [UP]);
+        assert_eq!(map.bindings(Action::Quit), [Z, W]);
+        // Unbinding something that isn't bound changes nothing.
+        map.unbind(Action::MoveLeft, W);
+        assert!(map.bindings(Action::MoveLeft).is_empty());
+    }
+
+    #[test]
+    fn rebind_moves_actions_to_a_free_input() {
+        let mut map = map();
+        let f = Binding::Key(KeyCode::KeyF);
+        assert_eq!(map.rebind(W, f), [Action::MoveForward]);
+        assert_eq!(map.bindings(Action::MoveForward), [UP, f]);
+        assert!(map.actions_bound_to(W).next().is_none());
+    }
+
+    #[test]
+    fn rebind_onto_a_used_input_swaps_them() {
+        let mut map = map();
+        assert_eq!(map.rebind(W, Z), [Action::MoveForward]);
+        assert_eq!(map.bindings(Action::MoveForward), [UP, Z]);
+        assert_eq!(map.bindings(Action::Quit), [W]);
+    }
+
+    #[test]
+    fn rebind_of_an_unbound_input_does_nothing() {
+        let mut map = map();
+        let f = Binding::Key(KeyCode::KeyF);
+        assert!(map.rebind(f, W).is_empty());
+        assert_eq!(map.bindings(Action::MoveForward), [W, UP]);
+        assert!(map.actions_bound_to(f).next().is_none());
+    }
+
+    #[test]
+    fn rebind_onto_itself_keeps_the_order() {
+        let mut map = map();
+        assert_eq!(map.rebind(W, W), [Action::MoveForward]);
+        assert_eq!(map.bindings(Action::MoveForward), [W, UP]);
+    }
+
+    #[test]
+    fn rebinding_takes_the_next_two_presses() {
+        let mut input = Input::new(map());
+        input.start_rebinding();
+        assert!(input.handle_binding(W, true).is_empty());
+        input.handle_binding(W, false);
+        assert!(input.handle_binding(Z, true).is_empty());
+        input.handle_binding(Z, false);
+        assert!(!input.is_rebinding());
+        assert_eq!(input.handle_binding(Z, true), [Action::MoveForward]);
+        assert_eq!(input.handle_binding(W, true), [Action::Quit]);
+    }
+
+    #[test]
+    fn rebinding_gives_up_on_an_unbound_input() {
+        let mut input = Input::new(map());
+        input.start_rebinding();
+        let f = Binding::Key(KeyCode::KeyF);
+        assert!(input.handle_binding(f, true).is_empty());
+        assert_eq!(input.handle_binding(W, true), [Action::MoveForward]);
+    }
+}
@@ -1,15 +1,23 @@
+use cgmath::{Matrix, SquareMatrix};
+use std::ops::Range;
+
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    /// Multiplied with the material's base color.
+    pub tint: [f32; 4],
 }
 
 impl Instance {
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-        }
+        InstanceRaw::new(self.to_matrix(), self.tint)
     }
 }
 
@@ -17,9 +25,28 @@ impl Instance {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 3]; 3],
+    pub tint: [f32; 4],
 }
 
 impl InstanceRaw {
+    pub fn new(model: cgmath::Matrix4<f32>, tint: [f32; 4]) -> Self {
+        let linear =
+            cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        // Normals transform by the inverse transpose so they stay
+        // perpendicular to surfaces under non-uniform scale. A flattened
+        // instance has no inverse; its normals are meaningless anyway.
+        let normal = linear
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or(linear);
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+            tint,
+        }
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -46,7 +73,231 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
+
+/// CPU-side instances mirrored into a GPU vertex buffer. Changes are tracked
+/// so `sync` only uploads what moved, and the buffer is reallocated when it
+/// runs out of room.
+pub struct InstanceBuffer {
+    instances: Vec<Instance>,
+    raw: Vec<InstanceRaw>,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    /// Raw instances that differ from what's on the GPU.
+    dirty: Option<Range<usize>>,
+    label: &'static str,
+}
+
+/// Grows `range` to also cover `extra`. Empty ranges add nothing.
+fn extend_range(range: &mut Option<Range<usize>>, extra: Range<usize>) {
+    if extra.is_empty() {
+        return;
+    }
+    *range = Some(match range.take() {
+        Some(range) => range.start.min(extra.start)..range.end.max(extra.end),
+        None => extra,
+    });
+}
+
+/// What `sync` has to write to the GPU buffer.
+#[derive(Debug, PartialEq)]
+enum Upload {
+    /// Reallocate with room for `capacity` instances and write all of them.
+    Everything { capacity: usize },
+    /// Write just these instances into the existing buffer.
+    Range(Range<usize>),
+}
+
+/// Works out the upload for `len` instances with `dirty` changed, when the
+/// buffer holds `capacity`. Outgrowing the buffer at least doubles it.
+fn plan_upload(len: usize, capacity: usize, dirty: Range<usize>) -> Option<Upload> {
+    if len > capacity {
+        Some(Upload::Everything {
+            capacity: len.max(capacity * 2),
+        })
+    } else {
+        let dirty = dirty.start..dirty.end.min(len);
+        (!dirty.is_empty()).then_some(Upload::Range(dirty))
+    }
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>, label: &'static str) -> Self {
+        let raw = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let capacity = raw.len().max(1);
+        let buffer = Self::create_buffer(device, capacity, label);
+        let mut instance_buffer = Self {
+            instances,
+            raw,
+            buffer,
+            capacity,
+            dirty: None,
+            label,
+        };
+        instance_buffer.mark_dirty(0..instance_buffer.instances.len());
+        instance_buffer
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize, label: &str) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        extend_range(&mut self.dirty, range);
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Instance range to pass to a draw call.
+    pub fn range(&self) -> Range<u32> {
+        0..self.instances.len() as u32
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Adds an instance and returns its index.
+    pub fn push(&mut self, instance: Instance) -> usize {
+        let index = self.instances.len();
+        self.raw.push(instance.to_raw());
+        self.instances.push(instance);
+        self.mark_dirty(index..index + 1);
+        index
+    }
+
+    /// Removes the instance at `index` by moving the last instance into its
+    /// slot, so only that one index changes. Instances placed by the scene
+    /// graph go through `SceneGraph::remove`, which follows the move.
+    pub fn swap_remove(&mut self, index: usize) -> Instance {
+        self.raw.swap_remove(index);
+        let instance = self.instances.swap_remove(index);
+        if index < self.instances.len() {
+            self.mark_dirty(index..index + 1);
+        }
+        instance
+    }
+
+    /// Places the instance at `index` with a precomputed world matrix, as the
+    /// scene graph does, instead of from its own position, rotation and scale.
+    pub fn set_world_matrix(&mut self, index: usize, world: cgmath::Matrix4<f32>) {
+        self.raw[index] = InstanceRaw::new(world, self.instances[index].tint);
+        self.mark_dirty(index..index + 1);
+    }
+
+    /// Uploads every instance changed since the last call, growing the GPU
+    /// buffer first if it's too small.
+    pub fn sync(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let Some(dirty) = self.dirty.take() else {
+            return;
+        };
+        match plan_upload(self.instances.len(), self.capacity, dirty) {
+            Some(Upload::Everything { capacity }) => {
+                self.capacity = capacity;
+                self.buffer = Self::create_buffer(device, capacity, self.label);
+                queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.raw));
+            }
+            Some(Upload::Range(dirty)) => {
+                let offset =
+                    (dirty.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+                queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&self.raw[dirty]));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extend_range_merges_into_one_span() {
+        let mut range = None;
+        extend_range(&mut range, 4..6);
+        assert_eq!(range, Some(4..6));
+        // Disjoint ranges take in everything between them.
+        extend_range(&mut range, 1..2);
+        assert_eq!(range, Some(1..6));
+        extend_range(&mut range, 5..9);
+        assert_eq!(range, Some(1..9));
+        extend_range(&mut range, 2..3);
+        assert_eq!(range, Some(1..9));
+    }
+
+    #[test]
+    fn extend_range_ignores_empty_ranges() {
+        let mut range = None;
+        extend_range(&mut range, 3..3);
+        assert_eq!(range, None);
+        extend_range(&mut range, 1..2);
+        extend_range(&mut range, 7..7);
+        assert_eq!(range, Some(1..2));
+    }
+
+    #[test]
+    fn upload_covers_only_the_dirty_range() {
+        assert_eq!(plan_upload(8, 8, 2..5), Some(Upload::Range(2..5)));
+    }
+
+    #[test]
+    fn upload_skips_instances_removed_since_marking() {
+        // A swap-remove of the last instance leaves its index marked.
+        assert_eq!(plan_upload(4, 8, 2..5), Some(Upload::Range(2..4)));
+        assert_eq!(plan_upload(4, 8, 4..5), None);
+    }
+
+    #[test]
+    fn outgrowing_the_buffer_doubles_it_and_uploads_everything() {
+        assert_eq!(
+            plan_upload(5, 4, 4..5),
+            Some(Upload::Everything { capacity: 8 })
+        );
+        assert_eq!(
+            plan_upload(9, 8, 8..9),
+            Some(Upload::Everything { capacity: 16 })
+        );
+    }
+
+    #[test]
+    fn growth_jumps_straight_to_a_large_instance_count() {
+        assert_eq!(
+            plan_upload(20, 4, 4..20),
+            Some(Upload::Everything { capacity: 20 })
+        );
+    }
+}
@@ -1,7 +1,25 @@
+pub(crate) mod animation;
 pub mod app;
+pub(crate) mod bloom;
 pub(crate) mod camera;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod capture;
+pub(crate) mod collision;
+pub(crate) mod fullscreen;
+pub(crate) mod game_loop;
+pub(crate) mod gamepad;
+pub(crate) mod hdr;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
+pub(crate) mod input;
 pub(crate) mod instance;
+pub(crate) mod light;
 pub(crate) mod model;
+pub(crate) mod recording;
+pub(crate) mod registry;
 pub(crate) mod resources;
+pub(crate) mod scene;
+pub(crate) mod scene_graph;
+pub(crate) mod shadow;
 pub(crate) mod state;
 pub(crate) mod texture;
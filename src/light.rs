@@ -0,0 +1,96 @@
+use cgmath::InnerSpace;
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+
+pub const MAX_POINT_LIGHTS: usize = 4;
+
+pub struct DirectionalLight {
+    pub direction: cgmath::Vector3<f32>,
+    pub color: [f32; 3],
+    /// Centre of the region the shadow map covers.
+    pub shadow_center: cgmath::Point3<f32>,
+    /// Radius around `shadow_center` that casts and receives shadows.
+    pub shadow_radius: f32,
+}
+
+impl DirectionalLight {
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let direction = self.direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+        let eye = self.shadow_center - direction * self.shadow_radius * 2.0;
+        let view = cgmath::Matrix4::look_at_rh(eye, self.shadow_center, up);
+
+        let r = self.shadow_radius;
+        let proj = cgmath::ortho(-r, r, -r, r, r, 3.0 * r);
+
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+}
+
+pub struct PointLight {
+    pub position: cgmath::Point3<f32>,
+    pub color: [f32; 3],
+    pub range: f32,
+}
+
+pub struct Lights {
+    pub ambient: [f32; 3],
+    pub sun: DirectionalLight,
+    pub points: Vec<PointLight>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLightRaw {
+    pub position: [f32; 3],
+    pub range: f32,
+    pub color: [f32; 3],
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    pub sun_view_proj: [[f32; 4]; 4],
+    pub ambient: [f32; 3],
+    pub num_point_lights: u32,
+    pub sun_direction: [f32; 3],
+    _padding: u32,
+    pub sun_color: [f32; 3],
+    _padding2: u32,
+    pub points: [PointLightRaw; MAX_POINT_LIGHTS],
+}
+
+impl LightUniform {
+    pub fn new() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+
+    pub fn update_lights(&mut self, lights: &Lights) {
+        if lights.points.len() > MAX_POINT_LIGHTS {
+            log::warn!(
+                "{} point lights requested, only the first {} are used",
+                lights.points.len(),
+                MAX_POINT_LIGHTS
+            );
+        }
+
+        self.sun_view_proj = lights.sun.build_view_projection_matrix().into();
+        self.ambient = lights.ambient;
+        self.sun_direction = lights.sun.direction.normalize().into();
+        self.sun_color = lights.sun.color;
+        self.num_point_lights = lights.points.len().min(MAX_POINT_LIGHTS) as u32;
+        for (raw, light) in self.points.iter_mut().zip(&lights.points) {
+            *raw = PointLightRaw {
+                position: light.position.into(),
+                range: light.range,
+                color: light.color,
+                _padding: 0,
+            };
+        }
+    }
+}
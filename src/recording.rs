@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::input::InputEvent;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimedEvent {
+    /// Simulation ticks run before the event arrived.
+    pub tick: u64,
+    pub event: InputEvent,
+}
+
+/// Every input event of a session, in order. With the fixed timestep, playing
+/// it back from the same scene retraces the session exactly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    /// Scene the session started in, relative to `res/`.
+    pub scene: String,
+    pub events: Vec<TimedEvent>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> anyhow::Result<Recording> {
+        let text = std::fs::read_to_string(path)?;
+        let recording = ron::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse recording {:?}: {}", path, e))?;
+        Ok(recording)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Where `State` gets its input: live from the app, live while keeping a
+/// recording, or from a recording with live input ignored.
+pub enum InputLog {
+    Live,
+    Record {
+        /// Events since the last `save`.
+        pending: Vec<TimedEvent>,
+        writer: RecordingWriter,
+    },
+    Replay {
+        events: VecDeque<TimedEvent>,
+    },
+}
+
+impl InputLog {
+    /// Records live input for `scene`, to be written to `path` by `save`
+    /// and `finish`.
+    pub fn record(path: PathBuf, scene: &str) -> Self {
+        InputLog::Record {
+            pending: Vec::new(),
+            writer: RecordingWriter::spawn(path, scene),
+        }
+    }
+
+    pub fn replay(recording: Recording) -> Self {
+        InputLog::Replay {
+            events: recording.events.into(),
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self, InputLog::Replay { .. })
+    }
+
+    /// Notes a live event that arrived after `tick` ticks and returns whether
+    /// to act on it.
+    pub fn live_event(&mut self, tick: u64, event: InputEvent) -> bool {
+        match self {
+            InputLog::Live => true,
+            InputLog::Record { pending, .. } => {
+                pending.push(TimedEvent { tick, event });
+                true
+            }
+            InputLog::Replay { .. } => false,
+        }
+    }
+
+    /// Recorded events that arrived after `tick` ticks, to feed in before
+    /// the next one. Live input takes over once the recording runs out.
+    pub fn due(&mut self, tick: u64) -> Vec<InputEvent> {
+        let InputLog::Replay { events } = self else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        while let Some(timed) = events.front()
+            && timed.tick <= tick
+        {
+            due.push(timed.event);
+            events.pop_front();
+        }
+        if events.is_empty() {
+            log::info!("Replay finished");
+            *self = InputLog::Live;
+        }
+        due
+    }
+
+    /// Hands events not yet written to the recording's writer, if there is
+    /// one. Called every few seconds while recording, so a crash loses
+    /// little.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if let InputLog::Record { pending, writer } = self
+            && !pending.is_empty()
+        {
+            writer.send(std::mem::take(pending))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rest of the recording, if there is one, and waits until
+    /// it's on disk. Input is live afterwards.
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        self.save()?;
+        if let InputLog::Record { writer, .. } = std::mem::replace(self, InputLog::Live) {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites a recording's file on another thread as events come in, so a long
+/// session doesn't hold up the tick that saves it.
+pub struct RecordingWriter {
+    events: mpsc::Sender<Vec<TimedEvent>>,
+    thread: JoinHandle<()>,
+}
+
+impl RecordingWriter {
+    fn spawn(path: PathBuf, scene: &str) -> Self {
+        let (events, received) = mpsc::channel::<Vec<TimedEvent>>();
+        let mut recording = Recording {
+            scene: scene.to_string(),
+            events: Vec::new(),
+        };
+        let thread = std::thread::spawn(move || {
+            for events in received {
+                recording.events.extend(events);
+                if let Err(e) = recording.save(&path) {
+                    log::error!("Unable to save input recording {:?}: {}", path, e);
+                }
+            }
+            log::info!(
+                "Recorded {} input events to {:?}",
+                recording.events.len(),
+                path
+            );
+        });
+        Self { events, thread }
+    }
+
+    fn send(&self, events: Vec<TimedEvent>) -> anyhow::Result<()> {
+        self.events
+            .send(events)
+            .map_err(|_| anyhow::anyhow!("Input recording writer stopped"))
+    }
+
+    /// Waits for everything sent so far to be written.
+    fn finish(self) -> anyhow::Result<()> {
+        drop(self.events);
+        self.thread
+            .join()
+            .map_err(|_| anyhow::anyhow!("Input recording writer panicked"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, CameraController, Projection};
+    use crate::game_loop::FixedTimestep;
+    use crate::input::{Binding, Input, InputMap};
+    use crate::texture::DepthMode;
+    use winit::keyboard::KeyCode;
+
+    fn key(tick: u64, pressed: bool) -> TimedEvent {
+        TimedEvent {
+            tick,
+            event: InputEvent::Key {
+                code: KeyCode::KeyW,
+                pressed,
+            },
+        }
+    }
+
+    #[test]
+    fn due_drains_events_up_to_the_tick_then_goes_live() {
+        let events = vec![key(0, true), key(2, false), key(2, true), key(5, false)];
+        let mut log = InputLog::replay(Recording {
+            scene: String::new(),
+            events: events.clone(),
+        });
+
+        assert_eq!(log.due(0), [events[0].event]);
+        assert!(log.due(1).is_empty());
+        assert_eq!(log.due(3), [events[1].event, events[2].event]);
+        assert!(matches!(log, InputLog::Replay { .. }));
+        assert!(!log.live_event(4, events[3].event));
+
+        assert_eq!(log.due(5), [events[3].event]);
+        assert!(matches!(log, InputLog::Live));
+        assert!(log.live_event(6, events[3].event));
+    }
+
+    #[test]
+    fn recording_round_trips_through_ron() {
+        let recording = Recording {
+            scene: "scenes/default.ron".to_string(),
+            events: vec![
+                key(0, true),
+                TimedEvent {
+                    tick: 3,
+                    event: InputEvent::MouseMotion {
+                        delta_x: 1.5,
+                        delta_y: -2.0,
+                    },
+                },
+                key(7, false),
+            ],
+        };
+        let text =
+            ron::ser::to_string_pretty(&recording, ron::ser::PrettyConfig::default()).unwrap();
+        assert_eq!(ron::from_str::<Recording>(&text).unwrap(), recording);
+    }
+
+    const TICKS_PER_SECOND: f32 = 60.0;
+
+    /// Flies the camera through a session the way `State::update` does,
+    /// taking `frame_time` seconds a frame. `live` events are handed to `log`
+    /// as they arrive, and before each tick `log` feeds in whatever it has
+    /// due. Returns where the camera was after each of the first `ticks`
+    /// ticks.
+    fn fly(
+        log: &mut InputLog,
+        live: &[TimedEvent],
+        frame_time: f32,
+        ticks: usize,
+    ) -> Vec<(cgmath::Point3<f32>, f32, f32)> {
+        let mut input = Input::new(InputMap::default());
+        let apply = |input: &mut Input, event| match event {
+            InputEvent::Key { code, pressed } => {
+                input.handle_binding(Binding::Key(code), pressed);
+            }
+            InputEvent::MouseMotion { delta_x, delta_y } => {
+                input.handle_mouse_moved(delta_x as f32, delta_y as f32)
+            }
+            _ => panic!("{:?} isn't used here", event),
+        };
+        let controller = CameraController::new(8.0, 0.1);
+        let mut camera = Camera::looking_at(
+            cgmath::Point3::new(0.0, 1.0, 5.0),
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+            Projection::Perspective {
+                aspect: 1.0,
+                fovy: 45.0,
+                znear: 0.1,
+                zfar: 100.0,
+            },
+            DepthMode::Standard,
+        );
+        let mut timestep = FixedTimestep::new(TICKS_PER_SECOND);
+        let mut live = live.iter().peekable();
+        let mut path = Vec::new();
+        while path.len() < ticks {
+            let tick = path.len() as u64;
+            while let Some(timed) = live.next_if(|timed| timed.tick <= tick) {
+                if log.live_event(tick, timed.event) {
+                    apply(&mut input, timed.event);
+                }
+            }
+            for _ in 0..timestep.advance(frame_time) {
+                for event in log.due(path.len() as u64) {
+                    apply(&mut input, event);
+                }
+                controller.update_camera(&mut camera, &input, timestep.tick());
+                input.end_tick();
+                path.push((camera.position, camera.yaw.0, camera.pitch.0));
+            }
+        }
+        path.truncate(ticks);
+        path
+    }
+
+    #[test]
+    fn replay_retraces_a_recorded_session_at_any_frame_rate() {
+        // Walks forward, looks around part way and then strafes.
+        let session = [
+            key(0, true),
+            TimedEvent {
+                tick: 12,
+                event: InputEvent::MouseMotion {
+                    delta_x: 40.0,
+                    delta_y: -10.0,
+                },
+            },
+            key(30, false),
+            TimedEvent {
+                tick: 31,
+                event: InputEvent::Key {
+                    code: KeyCode::KeyD,
+                    pressed: true,
+                },
+            },
+        ];
+        let ticks = 60;
+        let path = std::env::temp_dir().join(format!("recording-{}.ron", std::process::id()));
+
+        let mut log = InputLog::record(path.clone(), "scenes/default.ron");
+        let live = fly(&mut log, &session, 1.0 / TICKS_PER_SECOND, ticks);
+        log.finish().unwrap();
+        let recording = Recording::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(recording.events, session);
+
+        // Several ticks a frame, and frames that don't line up with ticks.
+        for frame_time in [3.0 / TICKS_PER_SECOND, 0.0213] {
+            let mut log = InputLog::replay(recording.clone());
+            let replayed = fly(&mut log, &[], frame_time, ticks);
+            assert!(
+                replayed == live,
+                "replaying at {}s a frame strayed from the recorded path",
+                frame_time
+            );
+        }
+        assert!(live[10] != live[40], "the session didn't move the camera");
+    }
+}
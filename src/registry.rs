@@ -0,0 +1,85 @@
+use crate::collision::Ray;
+use crate::instance::{Instance, InstanceBuffer};
+use crate::model::Model;
+
+/// Index of a model in a `ModelRegistry`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ModelId(usize);
+
+/// A loaded model together with everywhere it's placed in the world.
+pub struct ModelEntry {
+    pub model: Model,
+    pub instances: InstanceBuffer,
+}
+
+/// Every static model in the scene, each drawn with its own instance buffer.
+#[derive(Default)]
+pub struct ModelRegistry {
+    entries: Vec<ModelEntry>,
+}
+
+impl ModelRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        model: Model,
+        instances: Vec<Instance>,
+    ) -> ModelId {
+        let id = ModelId(self.entries.len());
+        self.entries.push(ModelEntry {
+            model,
+            instances: InstanceBuffer::new(device, instances, "Instance Buffer"),
+        });
+        id
+    }
+
+    #[allow(unused)]
+    pub fn get(&self, id: ModelId) -> &ModelEntry {
+        &self.entries[id.0]
+    }
+
+    /// Use `entry.instances` to add, move or remove placements; they're
+    /// uploaded on the next `sync`.
+    pub fn get_mut(&mut self, id: ModelId) -> &mut ModelEntry {
+        &mut self.entries[id.0]
+    }
+
+    /// Entries with at least one instance, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &ModelEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.instances.is_empty())
+    }
+
+    /// Distance along `ray` to the nearest surface closer than
+    /// `max_distance`, with a copy of each model placed at every matrix
+    /// `placements` gives for it.
+    pub fn raycast(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        placements: impl IntoIterator<Item = (ModelId, cgmath::Matrix4<f32>)>,
+    ) -> Option<f32> {
+        let mut nearest = None;
+        let mut limit = max_distance;
+        for (id, world) in placements {
+            let collision = &self.entries[id.0].model.collision;
+            if let Some(distance) = collision.raycast_instance(ray, limit, world) {
+                limit = distance;
+                nearest = Some(distance);
+            }
+        }
+        nearest
+    }
+
+    /// Uploads instance changes made since the last sync.
+    pub fn sync(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for entry in &mut self.entries {
+            entry.instances.sync(device, queue);
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use std::path::Path;
 
 use anyhow::Context;
+use base64::Engine;
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
 use futures_lite::io::BufReader;
 use image::{DynamicImage, Rgba, RgbaImage};
 use wgpu::util::DeviceExt;
 
-use crate::{model, texture};
+use crate::collision::CollisionMesh;
+use crate::{animation, model, texture};
 
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
@@ -55,18 +58,37 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
 
 pub async fn load_texture(
     file_name: &str,
+    is_linear: bool,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    texture::Texture::from_bytes(device, queue, &data, file_name, is_linear)
 }
 
+/// Loads an OBJ or glTF model, picking the loader from the file extension.
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("obj") => load_obj(file_name, device, queue, layout).await,
+        Some("gltf" | "glb") => load_gltf(file_name, device, queue, layout).await,
+        _ => anyhow::bail!("Unsupported model format: {}", file_name),
+    }
+}
+
+pub async fn load_obj(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name).await?;
     let obj_reader = BufReader::new(obj_text.as_bytes());
@@ -97,6 +119,7 @@ pub async fn load_model(
                 texture_file
                     .to_str()
                     .context("Invalid UTF-8 in texture path")?,
+                false,
                 device,
                 queue,
             )
@@ -112,69 +135,79 @@ pub async fn load_model(
             ]);
             let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, rgba));
             let label = format!("{}_kd_fallback", m.name);
-            texture::Texture::from_image(device, queue, &image, Some(&label))?
+            texture::Texture::from_image(device, queue, &image, Some(&label), false)?
+        };
+        let normal_texture = if let Some(normal_texture) = &m.normal_texture {
+            let texture_file = containing_folder.join(normal_texture);
+            load_texture(
+                texture_file
+                    .to_str()
+                    .context("Invalid UTF-8 in texture path")?,
+                true,
+                device,
+                queue,
+            )
+            .await
+            .with_context(|| format!("Error loading {}", texture_file.display()))?
+        } else {
+            let label = format!("{}_normal_fallback", m.name);
+            solid_texture(device, queue, FLAT_NORMAL, &label, true)?
+        };
+        let metallic_roughness_texture = {
+            let roughness = shininess_to_roughness(m.shininess.unwrap_or(DEFAULT_SHININESS));
+            let label = format!("{}_roughness_fallback", m.name);
+            solid_texture(
+                device,
+                queue,
+                Rgba([0, (roughness * 255.0) as u8, 0, 255]),
+                &label,
+                true,
+            )?
         };
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
 
-        materials.push(model::Material {
-            name: m.name,
+        materials.push(model::Material::new(
+            device,
+            &m.name,
             diffuse_texture,
-            bind_group,
-        })
+            normal_texture,
+            metallic_roughness_texture,
+            layout,
+        ))
     }
 
+    let mut collision = CollisionMesh::default();
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| {
-                    if m.mesh.normals.is_empty() {
-                        model::ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [0.0, 0.0, 0.0],
-                        }
+            let mut vertices = (0..m.mesh.positions.len() / 3)
+                .map(|i| model::ModelVertex {
+                    position: [
+                        m.mesh.positions[i * 3],
+                        m.mesh.positions[i * 3 + 1],
+                        m.mesh.positions[i * 3 + 2],
+                    ],
+                    tex_coords: if m.mesh.texcoords.is_empty() {
+                        [0.0, 0.0]
                     } else {
-                        model::ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [
-                                m.mesh.normals[i * 3],
-                                m.mesh.normals[i * 3 + 1],
-                                m.mesh.normals[i * 3 + 2],
-                            ],
-                        }
-                    }
+                        [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]]
+                    },
+                    normal: if m.mesh.normals.is_empty() {
+                        [0.0, 0.0, 0.0]
+                    } else {
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    },
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
                 })
                 .collect::<Vec<_>>();
 
+            compute_tangents(&mut vertices, &m.mesh.indices);
+            collision.extend(&m.mesh.indices, |i| vertices[i].position);
+
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
@@ -187,7 +220,6 @@ pub async fn load_model(
             });
 
             model::Mesh {
-                name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
@@ -202,5 +234,876 @@ pub async fn load_model(
         materials.len()
     );
 
-    Ok(model::Model { meshes, materials })
+    Ok(model::Model {
+        meshes,
+        materials,
+        collision,
+    })
+}
+
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let (gltf, buffers) = load_gltf_document(file_name).await?;
+    let materials = load_gltf_materials(file_name, &gltf, &buffers, device, queue, layout).await?;
+    let default_material = materials.len() - 1;
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .context("glTF file has no scenes")?;
+
+    let mut meshes = Vec::new();
+    let mut collision = CollisionMesh::default();
+    let mut nodes = scene
+        .nodes()
+        .map(|node| (node, cgmath::Matrix4::identity()))
+        .collect::<Vec<_>>();
+    while let Some((node, parent_transform)) = nodes.pop() {
+        let transform = parent_transform * cgmath::Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in triangle_primitives(&mesh, file_name) {
+                let (vertices, indices) = read_gltf_vertices(&primitive, &buffers, transform)?;
+                let material = primitive.material().index().unwrap_or(default_material);
+                let name = mesh.name().unwrap_or(file_name);
+                collision.extend(&indices, |i| vertices[i].position);
+                meshes.push(create_mesh(name, device, &vertices, &indices, material));
+            }
+        }
+
+        nodes.extend(node.children().map(|child| (child, transform)));
+    }
+
+    println!(
+        "Loaded model {:?} with {} meshes and {} materials",
+        file_name,
+        meshes.len(),
+        materials.len()
+    );
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        collision,
+    })
+}
+
+/// Loads the skinned meshes, first skin and animations of a glTF file. Mesh
+/// vertices stay in bind pose; node transforms are applied through the
+/// skeleton instead.
+pub async fn load_skinned_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::SkinnedModel> {
+    let (gltf, buffers) = load_gltf_document(file_name).await?;
+    let materials = load_gltf_materials(file_name, &gltf, &buffers, device, queue, layout).await?;
+    let default_material = materials.len() - 1;
+
+    let skin = gltf
+        .skins()
+        .next()
+        .with_context(|| format!("{} has no skin", file_name))?;
+    let skeleton = read_gltf_skeleton(&gltf, &skin, &buffers)?;
+    let clips = gltf
+        .animations()
+        .map(|animation| read_gltf_animation(&animation, &buffers))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut meshes = Vec::new();
+    for node in gltf.nodes().filter(|node| node.skin().is_some()) {
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        for primitive in triangle_primitives(&mesh, file_name) {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let joints = reader
+                .read_joints(0)
+                .context("Skinned glTF primitive has no JOINTS_0")?
+                .into_u16()
+                .map(|j| j.map(u32::from))
+                .collect::<Vec<_>>();
+            let weights = reader
+                .read_weights(0)
+                .context("Skinned glTF primitive has no WEIGHTS_0")?
+                .into_f32()
+                .collect::<Vec<_>>();
+
+            let (vertices, indices) =
+                read_gltf_vertices(&primitive, &buffers, cgmath::Matrix4::identity())?;
+            check_count("joints", Some(joints.len()), vertices.len())?;
+            check_count("weights", Some(weights.len()), vertices.len())?;
+            let vertices = vertices
+                .iter()
+                .zip(joints.iter().zip(&weights))
+                .map(|(v, (&joints, &weights))| {
+                    let total = weights.iter().sum::<f32>();
+                    model::SkinnedVertex {
+                        position: v.position,
+                        tex_coords: v.tex_coords,
+                        normal: v.normal,
+                        tangent: v.tangent,
+                        bitangent: v.bitangent,
+                        joints,
+                        weights: if total > 0.0 {
+                            weights.map(|w| w / total)
+                        } else {
+                            [1.0, 0.0, 0.0, 0.0]
+                        },
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let material = primitive.material().index().unwrap_or(default_material);
+            let name = mesh.name().unwrap_or(file_name);
+            meshes.push(create_mesh(name, device, &vertices, &indices, material));
+        }
+    }
+
+    println!(
+        "Loaded skinned model {:?} with {} meshes, {} joints and {} animations",
+        file_name,
+        meshes.len(),
+        skeleton.joints.len(),
+        clips.len()
+    );
+
+    Ok(model::SkinnedModel {
+        meshes,
+        materials,
+        skeleton,
+        clips,
+    })
+}
+
+async fn load_gltf_document(file_name: &str) -> anyhow::Result<(gltf::Gltf, Vec<Vec<u8>>)> {
+    let gltf_data = load_binary(file_name).await?;
+    let gltf = gltf::Gltf::from_slice(&gltf_data)
+        .with_context(|| format!("Error parsing {}", file_name))?;
+    let containing_folder = Path::new(file_name)
+        .parent()
+        .context("Model has no parent folder")?;
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .context("glTF buffer refers to a missing GLB binary chunk")?,
+            gltf::buffer::Source::Uri(uri) => load_gltf_uri(uri, containing_folder).await?,
+        };
+        buffers.push(data);
+    }
+
+    Ok((gltf, buffers))
+}
+
+/// Loads every material in the file, followed by the glTF default material
+/// (white, fully rough, non-metallic) for primitives that don't name one.
+async fn load_gltf_materials(
+    file_name: &str,
+    gltf: &gltf::Gltf,
+    buffers: &[Vec<u8>],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Vec<model::Material>> {
+    let containing_folder = Path::new(file_name)
+        .parent()
+        .context("Model has no parent folder")?;
+
+    let mut materials = Vec::new();
+    for material in gltf.materials() {
+        let name = material
+            .name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}_material_{}", file_name, materials.len()));
+        let pbr = material.pbr_metallic_roughness();
+
+        let base_color_factor = pbr.base_color_factor();
+        let base_color_srgb = [
+            linear_to_srgb(base_color_factor[0]),
+            linear_to_srgb(base_color_factor[1]),
+            linear_to_srgb(base_color_factor[2]),
+            base_color_factor[3],
+        ];
+        let diffuse_texture = match pbr.base_color_texture() {
+            Some(info) => {
+                let gltf_texture = info.texture();
+                let mut image =
+                    load_gltf_image(gltf_texture.source(), buffers, containing_folder).await?;
+                if base_color_srgb != [1.0; 4] {
+                    let mut rgba = image.to_rgba8();
+                    for pixel in rgba.pixels_mut() {
+                        for (channel, factor) in pixel.0.iter_mut().zip(base_color_srgb) {
+                            *channel = (*channel as f32 * factor) as u8;
+                        }
+                    }
+                    image = DynamicImage::ImageRgba8(rgba);
+                }
+                let mut texture =
+                    texture::Texture::from_image(device, queue, &image, Some(&name), false)?;
+                texture.sampler = gltf_sampler(device, &gltf_texture.sampler());
+                texture
+            }
+            None => solid_texture(
+                device,
+                queue,
+                Rgba(base_color_srgb.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8)),
+                &format!("{}_base_color_fallback", name),
+                false,
+            )?,
+        };
+
+        let normal_texture = match material.normal_texture() {
+            Some(normal) => {
+                let gltf_texture = normal.texture();
+                let image =
+                    load_gltf_image(gltf_texture.source(), buffers, containing_folder).await?;
+                let mut texture =
+                    texture::Texture::from_image(device, queue, &image, Some(&name), true)?;
+                texture.sampler = gltf_sampler(device, &gltf_texture.sampler());
+                texture
+            }
+            None => solid_texture(
+                device,
+                queue,
+                FLAT_NORMAL,
+                &format!("{}_normal_fallback", name),
+                true,
+            )?,
+        };
+
+        let roughness = pbr.roughness_factor();
+        let metallic = pbr.metallic_factor();
+        let metallic_roughness_texture = match pbr.metallic_roughness_texture() {
+            Some(info) => {
+                let gltf_texture = info.texture();
+                let image =
+                    load_gltf_image(gltf_texture.source(), buffers, containing_folder).await?;
+                let mut rgba = image.to_rgba8();
+                for pixel in rgba.pixels_mut() {
+                    pixel.0[1] = (pixel.0[1] as f32 * roughness) as u8;
+                    pixel.0[2] = (pixel.0[2] as f32 * metallic) as u8;
+                }
+                let image = DynamicImage::ImageRgba8(rgba);
+                let mut texture =
+                    texture::Texture::from_image(device, queue, &image, Some(&name), true)?;
+                texture.sampler = gltf_sampler(device, &gltf_texture.sampler());
+                texture
+            }
+            None => solid_texture(
+                device,
+                queue,
+                Rgba([
+                    0,
+                    (roughness.clamp(0.0, 1.0) * 255.0) as u8,
+                    (metallic.clamp(0.0, 1.0) * 255.0) as u8,
+                    255,
+                ]),
+                &format!("{}_metallic_roughness_fallback", name),
+                true,
+            )?,
+        };
+
+        materials.push(model::Material::new(
+            device,
+            &name,
+            diffuse_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            layout,
+        ));
+    }
+
+    let label = format!("{}_default_material", file_name);
+    materials.push(model::Material::new(
+        device,
+        &label,
+        solid_texture(device, queue, Rgba([255; 4]), &label, false)?,
+        solid_texture(device, queue, FLAT_NORMAL, &label, true)?,
+        solid_texture(device, queue, Rgba([0, 255, 0, 255]), &label, true)?,
+        layout,
+    ));
+
+    Ok(materials)
+}
+
+fn triangle_primitives<'a>(mesh: &gltf::Mesh<'a>, file_name: &str) -> Vec<gltf::Primitive<'a>> {
+    mesh.primitives()
+        .filter(|primitive| {
+            let is_triangles = primitive.mode() == gltf::mesh::Mode::Triangles;
+            if !is_triangles {
+                log::warn!(
+                    "Skipping {:?} primitive in {:?}, only triangles are supported",
+                    primitive.mode(),
+                    file_name
+                );
+            }
+            is_triangles
+        })
+        .collect()
+}
+
+fn read_gltf_skeleton(
+    gltf: &gltf::Gltf,
+    skin: &gltf::Skin,
+    buffers: &[Vec<u8>],
+) -> anyhow::Result<animation::Skeleton> {
+    let mut nodes = gltf
+        .nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            animation::SkeletonNode {
+                parent: None,
+                rest: animation::Transform {
+                    translation: translation.into(),
+                    rotation: cgmath::Quaternion::new(
+                        rotation[3],
+                        rotation[0],
+                        rotation[1],
+                        rotation[2],
+                    ),
+                    scale: scale.into(),
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    let children = gltf
+        .nodes()
+        .map(|node| {
+            node.children()
+                .map(|child| child.index())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    for (parent, children) in children.iter().enumerate() {
+        for &child in children {
+            nodes[child].parent = Some(parent);
+        }
+    }
+
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut stack = (0..nodes.len())
+        .filter(|&i| nodes[i].parent.is_none())
+        .collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        order.push(node);
+        stack.extend(&children[node]);
+    }
+
+    let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+    let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+        Some(matrices) => matrices.map(cgmath::Matrix4::from).collect(),
+        None => vec![cgmath::Matrix4::identity(); joints.len()],
+    };
+
+    Ok(animation::Skeleton {
+        nodes,
+        order,
+        joints,
+        inverse_bind_matrices,
+    })
+}
+
+fn read_gltf_animation(
+    animation: &gltf::Animation,
+    buffers: &[Vec<u8>],
+) -> anyhow::Result<animation::AnimationClip> {
+    use gltf::animation::util::ReadOutputs;
+
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let times = reader
+            .read_inputs()
+            .context("Animation channel has no keyframe times")?
+            .collect::<Vec<_>>();
+        let interpolation = match channel.sampler().interpolation() {
+            gltf::animation::Interpolation::Step => animation::Interpolation::Step,
+            gltf::animation::Interpolation::Linear => animation::Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => {
+                log::warn!("Cubic spline keyframes are sampled linearly");
+                animation::Interpolation::Linear
+            }
+        };
+        // Cubic splines store an in-tangent, value and out-tangent per key.
+        let stride =
+            if channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline {
+                3
+            } else {
+                1
+            };
+        let keyframes = match reader.read_outputs() {
+            Some(ReadOutputs::Translations(values)) => animation::Keyframes::Translation(
+                values
+                    .skip(stride / 2)
+                    .step_by(stride)
+                    .map(Into::into)
+                    .collect(),
+            ),
+            Some(ReadOutputs::Rotations(values)) => animation::Keyframes::Rotation(
+                values
+                    .into_f32()
+                    .skip(stride / 2)
+                    .step_by(stride)
+                    .map(|[x, y, z, w]| cgmath::Quaternion::new(w, x, y, z))
+                    .collect(),
+            ),
+            Some(ReadOutputs::Scales(values)) => animation::Keyframes::Scale(
+                values
+                    .skip(stride / 2)
+                    .step_by(stride)
+                    .map(Into::into)
+                    .collect(),
+            ),
+            Some(ReadOutputs::MorphTargetWeights(_)) => {
+                log::warn!("Skipping morph target animation channel");
+                continue;
+            }
+            None => continue,
+        };
+
+        channels.push(animation::Channel {
+            node: channel.target().node().index(),
+            interpolation,
+            times,
+            keyframes,
+        });
+    }
+
+    let duration = channels
+        .iter()
+        .filter_map(|channel| channel.times.last().copied())
+        .fold(0.0, f32::max);
+
+    Ok(animation::AnimationClip {
+        name: animation.name().unwrap_or("animation").to_string(),
+        duration,
+        channels,
+    })
+}
+
+/// Reads a buffer or image URI, either inline as a base64 `data:` URI or as a
+/// file relative to the glTF file.
+async fn load_gltf_uri(uri: &str, containing_folder: &Path) -> anyhow::Result<Vec<u8>> {
+    if let Some(data_uri) = uri.strip_prefix("data:") {
+        let (_, data) = data_uri
+            .split_once(";base64,")
+            .context("Only base64 data URIs are supported")?;
+        return Ok(base64::engine::general_purpose::STANDARD.decode(data)?);
+    }
+
+    let file = containing_folder.join(uri);
+    load_binary(file.to_str().context("Invalid UTF-8 in buffer path")?)
+        .await
+        .with_context(|| format!("Error loading {}", file.display()))
+}
+
+async fn load_gltf_image(
+    image: gltf::Image<'_>,
+    buffers: &[Vec<u8>],
+    containing_folder: &Path,
+) -> anyhow::Result<DynamicImage> {
+    let data = match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let range = view.offset()..view.offset() + view.length();
+            buffers
+                .get(view.buffer().index())
+                .and_then(|buffer| buffer.get(range))
+                .with_context(|| format!("Image buffer view {} is out of range", view.index()))?
+                .to_vec()
+        }
+        gltf::image::Source::Uri { uri, .. } => load_gltf_uri(uri, containing_folder).await?,
+    };
+    Ok(image::load_from_memory(&data)?)
+}
+
+fn gltf_sampler(device: &wgpu::Device, sampler: &gltf::texture::Sampler) -> wgpu::Sampler {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+        _ => wgpu::FilterMode::Linear,
+    };
+    let min_filter = match sampler.min_filter() {
+        Some(
+            MinFilter::Nearest | MinFilter::NearestMipmapNearest | MinFilter::NearestMipmapLinear,
+        ) => wgpu::FilterMode::Nearest,
+        _ => wgpu::FilterMode::Linear,
+    };
+
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter,
+        min_filter,
+        mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+        ..Default::default()
+    })
+}
+
+/// Reads the vertices and indices of a triangle primitive, with `transform`
+/// baked into them.
+fn read_gltf_vertices(
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    transform: cgmath::Matrix4<f32>,
+) -> anyhow::Result<(Vec<model::ModelVertex>, Vec<u32>)> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+
+    let positions = reader
+        .read_positions()
+        .context("glTF primitive has no positions")?
+        .collect::<Vec<_>>();
+    let normals = reader.read_normals().map(Iterator::collect::<Vec<_>>);
+    let tex_coords = reader
+        .read_tex_coords(0)
+        .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>());
+    let tangents = reader.read_tangents().map(Iterator::collect::<Vec<_>>);
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    for (name, count) in [
+        ("normals", normals.as_ref().map(Vec::len)),
+        ("texture coordinates", tex_coords.as_ref().map(Vec::len)),
+        ("tangents", tangents.as_ref().map(Vec::len)),
+    ] {
+        check_count(name, count, positions.len())?;
+    }
+    check_indices(&indices, positions.len())?;
+
+    let linear = cgmath::Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    let normal_matrix = linear
+        .invert()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or(linear);
+
+    let mut vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let position = transform * cgmath::Point3::from(*position).to_homogeneous();
+            let normal = normals
+                .as_ref()
+                .map(|normals| (normal_matrix * cgmath::Vector3::from(normals[i])).normalize())
+                .unwrap_or(cgmath::Vector3::new(0.0, 0.0, 0.0));
+            model::ModelVertex {
+                position: position.truncate().into(),
+                tex_coords: tex_coords.as_ref().map_or([0.0, 0.0], |uvs| uvs[i]),
+                normal: normal.into(),
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // A mirroring transform turns the triangles inside out.
+    if linear.determinant() < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+    if normals.is_none() {
+        compute_normals(&mut vertices, &indices);
+    }
+
+    match tangents {
+        Some(tangents) if normals.is_some() => {
+            for (v, tangent) in vertices.iter_mut().zip(tangents) {
+                let normal = cgmath::Vector3::from(v.normal);
+                let t = (linear * cgmath::Vector4::from(tangent).truncate()).normalize();
+                v.tangent = t.into();
+                v.bitangent = (normal.cross(t) * tangent[3]).into();
+            }
+        }
+        _ => compute_tangents(&mut vertices, &indices),
+    }
+
+    Ok((vertices, indices))
+}
+
+/// Fails unless a vertex attribute, if present, has `count` entries, one per
+/// position.
+fn check_count(name: &str, count: Option<usize>, positions: usize) -> anyhow::Result<()> {
+    match count {
+        Some(count) if count != positions => anyhow::bail!(
+            "glTF primitive has {} {} for {} positions",
+            count,
+            name,
+            positions
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if any of `indices` points past the last of `vertex_count` vertices.
+fn check_indices(indices: &[u32], vertex_count: usize) -> anyhow::Result<()> {
+    match indices
+        .iter()
+        .find(|&&index| index as usize >= vertex_count)
+    {
+        Some(index) => anyhow::bail!(
+            "Vertex index {} is out of range for {} vertices",
+            index,
+            vertex_count
+        ),
+        None => Ok(()),
+    }
+}
+
+fn create_mesh<V: bytemuck::Pod>(
+    name: &str,
+    device: &wgpu::Device,
+    vertices: &[V],
+    indices: &[u32],
+    material: usize,
+) -> model::Mesh {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", name)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    model::Mesh {
+        vertex_buffer,
+        index_buffer,
+        num_elements: indices.len() as u32,
+        material,
+    }
+}
+
+/// Normal map texel pointing straight out of the surface.
+const FLAT_NORMAL: Rgba<u8> = Rgba([128, 128, 255, 255]);
+
+/// Blinn-Phong exponent used when an OBJ material doesn't set `Ns`.
+const DEFAULT_SHININESS: f32 = 32.0;
+
+/// Inverse of the roughness to Blinn-Phong exponent mapping in `shader.wgsl`.
+fn shininess_to_roughness(shininess: f32) -> f32 {
+    let alpha = (2.0 / (shininess.max(0.0) + 2.0)).sqrt();
+    alpha.sqrt().clamp(0.0, 1.0)
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// 1x1 texture of a single color, for materials missing one of their maps.
+fn solid_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    rgba: Rgba<u8>,
+    label: &str,
+    is_linear: bool,
+) -> anyhow::Result<texture::Texture> {
+    let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, rgba));
+    texture::Texture::from_image(device, queue, &image, Some(label), is_linear)
+}
+
+/// Fills in `normal` for every vertex, for meshes that come without them, as
+/// the average of the triangles that use it weighted by their area.
+fn compute_normals(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    for c in indices.chunks_exact(3) {
+        let [p0, p1, p2] =
+            [c[0], c[1], c[2]].map(|i| cgmath::Vector3::from(vertices[i as usize].position));
+        // Twice the triangle's area long.
+        let face = (p1 - p0).cross(p2 - p0);
+        for &i in c {
+            let v = &mut vertices[i as usize];
+            v.normal = (cgmath::Vector3::from(v.normal) + face).into();
+        }
+    }
+    for v in vertices {
+        let normal = cgmath::Vector3::from(v.normal);
+        if normal.magnitude2() > 0.0 {
+            v.normal = normal.normalize().into();
+        }
+    }
+}
+
+/// Fills in `tangent` and `bitangent` for every vertex from the triangle
+/// positions and UVs. Each vertex gets the average over the triangles that
+/// use it, re-orthogonalised against its normal.
+fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0u32; vertices.len()];
+
+    for c in indices.chunks_exact(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if det.abs() < f32::EPSILON {
+            // No usable UV mapping on this triangle.
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // Texture coordinates have a top-left origin but normal maps are
+        // authored with +Y pointing up the texture, so the bitangent is flipped.
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        for &i in c {
+            let v = &mut vertices[i as usize];
+            v.tangent = (tangent + cgmath::Vector3::from(v.tangent)).into();
+            v.bitangent = (bitangent + cgmath::Vector3::from(v.bitangent)).into();
+            triangles_included[i as usize] += 1;
+        }
+    }
+
+    for (v, n) in vertices.iter_mut().zip(triangles_included) {
+        let normal = cgmath::Vector3::from(v.normal);
+        if normal.magnitude2() == 0.0 {
+            continue;
+        }
+        let normal = normal.normalize();
+
+        let tangent = cgmath::Vector3::from(v.tangent);
+        let tangent = tangent - normal * normal.dot(tangent);
+
+        let (tangent, bitangent) = if n == 0 || tangent.magnitude2() < f32::EPSILON {
+            // Any basis works here since the flat normal map won't tilt it.
+            let helper = if normal.x.abs() < 0.9 {
+                cgmath::Vector3::unit_x()
+            } else {
+                cgmath::Vector3::unit_y()
+            };
+            let tangent = helper.cross(normal).normalize();
+            (tangent, normal.cross(tangent))
+        } else {
+            let tangent = tangent.normalize();
+            let handedness = if normal.cross(tangent).dot(v.bitangent.into()) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            (tangent, normal.cross(tangent) * handedness)
+        };
+
+        v.tangent = tangent.into();
+        v.bitangent = bitangent.into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A glTF document with one triangle in the XY plane, wound to face +Z,
+    /// and the buffer holding it. It has `normals` normals and the given
+    /// indices.
+    fn triangle(normals: usize, indices: &[u16]) -> (gltf::Gltf, Vec<u8>) {
+        let mut buffer = Vec::new();
+        for position in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            buffer.extend(bytemuck::cast_slice(&position));
+        }
+        for _ in 0..normals {
+            buffer.extend(bytemuck::cast_slice(&[0.0f32, 0.0, 1.0]));
+        }
+        let index_offset = buffer.len();
+        buffer.extend(bytemuck::cast_slice(indices));
+
+        let normal_attribute = if normals > 0 { r#", "NORMAL": 1"# } else { "" };
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": {buffer_length} }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": {normal_length} }},
+                    {{ "buffer": 0, "byteOffset": {index_offset}, "byteLength": {index_length} }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 0] }},
+                    {{ "bufferView": 1, "componentType": 5126, "count": {normals}, "type": "VEC3" }},
+                    {{ "bufferView": 2, "componentType": 5123, "count": {index_count}, "type": "SCALAR" }}
+                ],
+                "meshes": [{{ "primitives": [{{
+                    "attributes": {{ "POSITION": 0{normal_attribute} }},
+                    "indices": 2
+                }}] }}]
+            }}"#,
+            buffer_length = buffer.len(),
+            normal_length = (normals * 12).max(1),
+            index_length = indices.len() * 2,
+            index_count = indices.len(),
+        );
+        (gltf::Gltf::from_slice(json.as_bytes()).unwrap(), buffer)
+    }
+
+    fn read(normals: usize, indices: &[u16]) -> anyhow::Result<Vec<model::ModelVertex>> {
+        let (gltf, buffer) = triangle(normals, indices);
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        read_gltf_vertices(&primitive, &[buffer], cgmath::Matrix4::identity())
+            .map(|(vertices, _)| vertices)
+    }
+
+    #[test]
+    fn reads_a_well_formed_primitive() {
+        let vertices = read(3, &[0, 1, 2]).unwrap();
+        assert_eq!(vertices.len(), 3);
+        assert!(vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn rejects_attributes_short_of_the_positions() {
+        let error = read(2, &[0, 1, 2]).unwrap_err();
+        assert!(error.to_string().contains("2 normals for 3 positions"));
+    }
+
+    #[test]
+    fn rejects_indices_past_the_last_vertex() {
+        let error = read(3, &[0, 1, 5]).unwrap_err();
+        assert!(error.to_string().contains("index 5 is out of range"));
+    }
+
+    #[test]
+    fn computes_missing_normals_from_the_triangles() {
+        let vertices = read(0, &[0, 1, 2]).unwrap();
+        assert!(vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+    }
 }
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use cgmath::Rotation3;
+use serde::Deserialize;
+
+use crate::animation::Transform;
+use crate::bloom::BloomSettings;
+use crate::camera::{Camera, Projection};
+use crate::hdr::Tonemapping;
+use crate::instance::Instance;
+use crate::light::{DirectionalLight, Lights, PointLight};
+use crate::resources;
+use crate::scene_graph::{InstanceSlot, NodeId, SceneGraph};
+use crate::texture::DepthMode;
+
+/// A level as written by hand in a RON file under `res/`: which models to
+/// load, where to place them, and how the camera and lights start out.
+#[derive(Debug, Deserialize)]
+pub struct Scene {
+    pub camera: CameraDesc,
+    pub lights: LightsDesc,
+    /// How the scene's HDR color is brought into display range.
+    #[serde(default)]
+    pub tonemapping: Tonemapping,
+    #[serde(default)]
+    pub bloom: BloomSettings,
+    #[serde(default)]
+    pub models: Vec<ModelDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CameraDesc {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    #[serde(default = "default_fovy")]
+    pub fovy: f32,
+    #[serde(default = "default_znear")]
+    pub znear: f32,
+    #[serde(default = "default_zfar")]
+    pub zfar: f32,
+    /// Start with the orthographic projection instead of perspective.
+    #[serde(default)]
+    pub orthographic: bool,
+    /// Height of the orthographic view in world units.
+    #[serde(default = "default_ortho_height")]
+    pub ortho_height: f32,
+    /// Use reversed depth with an infinite far plane, for large open levels.
+    /// `zfar` then only applies to the orthographic projection.
+    #[serde(default)]
+    pub reverse_z: bool,
+    /// Makes the follow camera mode available, trailing the named instance.
+    #[serde(default)]
+    pub follow: Option<FollowDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FollowDesc {
+    pub target: String,
+    #[serde(default = "default_follow_offset")]
+    pub offset: [f32; 3],
+    #[serde(default = "default_look_height")]
+    pub look_height: f32,
+    #[serde(default = "default_stiffness")]
+    pub stiffness: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LightsDesc {
+    pub ambient: [f32; 3],
+    pub sun: SunDesc,
+    #[serde(default)]
+    pub points: Vec<PointLightDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SunDesc {
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    /// Centre and radius of the region the shadow map covers.
+    pub shadow_center: [f32; 3],
+    pub shadow_radius: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PointLightDesc {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub range: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModelDesc {
+    /// Path relative to `res/`, either an OBJ or a glTF file.
+    pub path: String,
+    /// Load the model's skin and play its first animation. glTF only.
+    #[serde(default)]
+    pub animated: bool,
+    pub instances: Vec<InstanceDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InstanceDesc {
+    /// Lets other instances name this one as their `parent`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Name of the instance this one is attached to. Position, rotation and
+    /// scale are then relative to it.
+    #[serde(default)]
+    pub parent: Option<String>,
+    pub position: [f32; 3],
+    /// Euler angles in degrees, applied about X, then Y, then Z.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default)]
+    pub scale: ScaleDesc,
+    /// RGBA multiplied with the model's base color.
+    #[serde(default = "default_tint")]
+    pub tint: [f32; 4],
+    /// Degrees per second to keep turning about the vertical axis.
+    #[serde(default)]
+    pub spin: f32,
+}
+
+/// Either one factor for all three axes or a factor per axis.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ScaleDesc {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+impl Default for ScaleDesc {
+    fn default() -> Self {
+        ScaleDesc::Uniform(1.0)
+    }
+}
+
+fn default_fovy() -> f32 {
+    45.0
+}
+
+fn default_znear() -> f32 {
+    0.1
+}
+
+fn default_zfar() -> f32 {
+    1000.0
+}
+
+fn default_ortho_height() -> f32 {
+    10.0
+}
+
+fn default_follow_offset() -> [f32; 3] {
+    [0.0, 2.0, 6.0]
+}
+
+fn default_look_height() -> f32 {
+    1.0
+}
+
+fn default_stiffness() -> f32 {
+    8.0
+}
+
+fn default_tint() -> [f32; 4] {
+    [1.0; 4]
+}
+
+impl Scene {
+    pub async fn load(file_name: &str) -> anyhow::Result<Scene> {
+        let text = resources::load_string(file_name).await?;
+        let scene = ron::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse scene {:?}: {}", file_name, e))?;
+        Ok(scene)
+    }
+}
+
+impl CameraDesc {
+    /// The camera in its starting projection, plus the other projection the
+    /// scene can toggle to.
+    pub fn to_camera(&self, aspect: f32) -> (Camera, Projection) {
+        let perspective = Projection::Perspective {
+            aspect,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        };
+        let orthographic = Projection::Orthographic {
+            aspect,
+            height: self.ortho_height,
+            znear: self.znear,
+            zfar: self.zfar,
+        };
+        let (projection, other) = if self.orthographic {
+            (orthographic, perspective)
+        } else {
+            (perspective, orthographic)
+        };
+        let depth_mode = if self.reverse_z {
+            DepthMode::Reversed
+        } else {
+            DepthMode::Standard
+        };
+        let camera =
+            Camera::looking_at(self.eye.into(), self.target.into(), projection, depth_mode);
+        (camera, other)
+    }
+}
+
+impl LightsDesc {
+    pub fn to_lights(&self) -> Lights {
+        Lights {
+            ambient: self.ambient,
+            sun: DirectionalLight {
+                direction: self.sun.direction.into(),
+                color: self.sun.color,
+                shadow_center: self.sun.shadow_center.into(),
+                shadow_radius: self.sun.shadow_radius,
+            },
+            points: self
+                .points
+                .iter()
+                .map(|point| PointLight {
+                    position: point.position.into(),
+                    color: point.color,
+                    range: point.range,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl InstanceDesc {
+    pub fn to_transform(&self) -> Transform {
+        let [x, y, z] = self.rotation;
+        let rotation = cgmath::Quaternion::from_angle_z(cgmath::Deg(z))
+            * cgmath::Quaternion::from_angle_y(cgmath::Deg(y))
+            * cgmath::Quaternion::from_angle_x(cgmath::Deg(x));
+        Transform {
+            translation: self.position.into(),
+            rotation,
+            scale: match self.scale {
+                ScaleDesc::Uniform(scale) => cgmath::Vector3::new(scale, scale, scale),
+                ScaleDesc::PerAxis(scale) => scale.into(),
+            },
+        }
+    }
+
+    pub fn to_instance(&self) -> Instance {
+        let transform = self.to_transform();
+        Instance {
+            position: transform.translation,
+            rotation: transform.rotation,
+            scale: transform.scale,
+            tint: self.tint,
+        }
+    }
+}
+
+/// Builds the scene graph for every instance in the scene, resolving `parent`
+/// names. Each instance comes with the slot it was loaded into, or `None` if
+/// its model was skipped. Also returns each instance's node, in the same
+/// order.
+pub fn build_scene_graph(
+    placements: &[(Option<InstanceSlot>, &InstanceDesc)],
+) -> anyhow::Result<(SceneGraph, Vec<NodeId>)> {
+    let mut names = HashMap::new();
+    for (index, (_, desc)) in placements.iter().enumerate() {
+        if let Some(name) = &desc.name
+            && names.insert(name.as_str(), index).is_some()
+        {
+            anyhow::bail!("More than one instance is named {:?}", name);
+        }
+    }
+
+    let mut graph = SceneGraph::new();
+    let mut nodes = vec![None; placements.len()];
+    for index in 0..placements.len() {
+        add_node(index, placements, &names, &mut nodes, &mut graph, 0)?;
+    }
+    Ok((graph, nodes.into_iter().flatten().collect()))
+}
+
+fn add_node(
+    index: usize,
+    placements: &[(Option<InstanceSlot>, &InstanceDesc)],
+    names: &HashMap<&str, usize>,
+    nodes: &mut [Option<NodeId>],
+    graph: &mut SceneGraph,
+    depth: usize,
+) -> anyhow::Result<NodeId> {
+    if let Some(node) = nodes[index] {
+        return Ok(node);
+    }
+    // Any chain longer than the number of instances must loop.
+    if depth > placements.len() {
+        anyhow::bail!("Instance parents form a cycle");
+    }
+
+    let (slot, desc) = &placements[index];
+    let parent = match &desc.parent {
+        Some(name) => {
+            let Some(&parent) = names.get(name.as_str()) else {
+                anyhow::bail!("Unknown parent instance {:?}", name);
+            };
+            Some(add_node(
+                parent,
+                placements,
+                names,
+                nodes,
+                graph,
+                depth + 1,
+            )?)
+        }
+        None => None,
+    };
+
+    let node = graph.add(parent, desc.name.as_deref(), desc.to_transform(), *slot);
+    nodes[index] = Some(node);
+    Ok(node)
+}
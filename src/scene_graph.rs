@@ -0,0 +1,480 @@
+use std::collections::{BTreeSet, HashMap};
+
+use cgmath::SquareMatrix;
+
+use crate::animation::Transform;
+use crate::registry::ModelId;
+
+/// Index of a node in a `SceneGraph`. Slots are reused once a node is
+/// removed, so the id also carries the slot's generation, to catch ids kept
+/// past their node.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+/// The instance a node places in the world.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InstanceSlot {
+    Static {
+        model: ModelId,
+        index: usize,
+    },
+    /// Index into `State::animated_models`.
+    Animated {
+        model: usize,
+        index: usize,
+    },
+}
+
+impl InstanceSlot {
+    pub fn index(self) -> usize {
+        match self {
+            Self::Static { index, .. } | Self::Animated { index, .. } => index,
+        }
+    }
+
+    /// The same buffer, at another index.
+    fn with_index(self, index: usize) -> Self {
+        match self {
+            Self::Static { model, .. } => Self::Static { model, index },
+            Self::Animated { model, .. } => Self::Animated { model, index },
+        }
+    }
+}
+
+pub struct Node {
+    name: Option<String>,
+    local: Transform,
+    /// `local` as of the start of the current tick, for blending between
+    /// ticks when drawing.
+    previous: Transform,
+    world: cgmath::Matrix4<f32>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    instance: Option<InstanceSlot>,
+    /// Set when this node moved since the last update.
+    dirty: bool,
+    /// Whether the last update recomputed `world`.
+    moved: bool,
+}
+
+/// A place for a node, empty once it's been removed.
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// Nodes with transforms relative to their parent. `update` turns them into
+/// world matrices, redoing only the subtrees that moved, and hands them to
+/// whichever instance each node places. Nodes moved during a tick glide there
+/// over the following frames, as set by `update`'s `alpha`.
+#[derive(Default)]
+pub struct SceneGraph {
+    /// Parents always come before their children, so a single pass in index
+    /// order sees every parent's world matrix before it's needed.
+    slots: Vec<Slot>,
+    /// Empty slots, to be filled before growing `slots`.
+    free: BTreeSet<usize>,
+    names: HashMap<String, NodeId>,
+    /// The node placing each instance.
+    placed_by: HashMap<InstanceSlot, NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node under `parent`, or at the root. A `name` lets the node be
+    /// looked up with `find`.
+    pub fn add(
+        &mut self,
+        parent: Option<NodeId>,
+        name: Option<&str>,
+        local: Transform,
+        instance: Option<InstanceSlot>,
+    ) -> NodeId {
+        // Only a slot past the parent's keeps parents ahead of children.
+        let after = parent.map_or(0, |parent| parent.index + 1);
+        let index = match self.free.range(after..).next() {
+            Some(&index) => {
+                self.free.remove(&index);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: None,
+                });
+                self.slots.len() - 1
+            }
+        };
+        let slot = &mut self.slots[index];
+        let id = NodeId {
+            index,
+            generation: slot.generation,
+        };
+        if let Some(name) = name {
+            self.names.insert(name.to_string(), id);
+        }
+        if let Some(instance) = instance {
+            self.placed_by.insert(instance, id);
+        }
+        slot.node = Some(Node {
+            name: name.map(str::to_string),
+            local,
+            previous: local,
+            world: local.to_matrix(),
+            parent,
+            children: Vec::new(),
+            instance,
+            dirty: true,
+            moved: false,
+        });
+        if let Some(parent) = parent {
+            self.node_mut(parent).children.push(id);
+        }
+        id
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        let slot = &self.slots[id.index];
+        match &slot.node {
+            Some(node) if slot.generation == id.generation => node,
+            _ => panic!("{:?} has been removed", id),
+        }
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        let slot = &mut self.slots[id.index];
+        match &mut slot.node {
+            Some(node) if slot.generation == id.generation => node,
+            _ => panic!("{:?} has been removed", id),
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn local(&self, id: NodeId) -> &Transform {
+        &self.node(id).local
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: Transform) {
+        let node = self.node_mut(id);
+        node.local = local;
+        node.dirty = true;
+    }
+
+    /// World matrix as of the last `update`, blended between ticks.
+    #[allow(unused)]
+    pub fn world(&self, id: NodeId) -> cgmath::Matrix4<f32> {
+        self.node(id).world
+    }
+
+    /// World matrix from the current local transforms, ignoring blending
+    /// and pending updates.
+    pub fn current_world(&self, id: NodeId) -> cgmath::Matrix4<f32> {
+        let node = self.node(id);
+        let local = node.local.to_matrix();
+        match node.parent {
+            Some(parent) => self.current_world(parent) * local,
+            None => local,
+        }
+    }
+
+    /// Every instance with its world matrix from the current local
+    /// transforms, as `current_world` gives, for queries made during a tick.
+    pub fn current_instances(&self) -> Vec<(InstanceSlot, cgmath::Matrix4<f32>)> {
+        let mut worlds = Vec::with_capacity(self.slots.len());
+        let mut instances = Vec::new();
+        for slot in &self.slots {
+            let Some(node) = &slot.node else {
+                worlds.push(cgmath::Matrix4::identity());
+                continue;
+            };
+            let local = node.local.to_matrix();
+            let world = match node.parent {
+                Some(parent) => worlds[parent.index] * local,
+                None => local,
+            };
+            worlds.push(world);
+            if let Some(instance) = node.instance {
+                instances.push((instance, world));
+            }
+        }
+        instances
+    }
+
+    #[allow(unused)]
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    #[allow(unused)]
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.node(id).children
+    }
+
+    /// Removes `id` and everything under it, freeing their slots for new
+    /// nodes. `swap_remove` is handed each instance they placed, to remove
+    /// with `InstanceBuffer::swap_remove`, and returns how many instances its
+    /// buffer has left. That moves the buffer's last instance into the freed
+    /// slot, so the node placing it is pointed there.
+    pub fn remove(&mut self, id: NodeId, mut swap_remove: impl FnMut(InstanceSlot) -> usize) {
+        if let Some(parent) = self.node(id).parent {
+            self.node_mut(parent).children.retain(|&child| child != id);
+        }
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let slot = &mut self.slots[id.index];
+            let node = slot.node.take().unwrap();
+            slot.generation += 1;
+            self.free.insert(id.index);
+            if let Some(name) = &node.name {
+                self.names.remove(name);
+            }
+            if let Some(instance) = node.instance {
+                self.placed_by.remove(&instance);
+                let len = swap_remove(instance);
+                if instance.index() != len {
+                    let moved = instance.with_index(len);
+                    if let Some(mover) = self.placed_by.remove(&moved) {
+                        self.node_mut(mover).instance = Some(instance);
+                        self.placed_by.insert(instance, mover);
+                    }
+                }
+            }
+            pending.extend(node.children);
+        }
+    }
+
+    /// Starts a simulation tick: where every node is now becomes where it
+    /// moves from.
+    pub fn begin_tick(&mut self) {
+        for node in self.slots.iter_mut().filter_map(|slot| slot.node.as_mut()) {
+            if node.previous != node.local {
+                node.previous = node.local;
+                // Draw it exactly at `local` once the blend is over.
+                node.dirty = true;
+            }
+        }
+    }
+
+    /// Recomputes the world matrices of every node that moved and calls
+    /// `place` for each of them that has an instance. Nodes moved this tick
+    /// are drawn `alpha` of the way from where they were to where they are.
+    pub fn update(
+        &mut self,
+        alpha: f32,
+        mut place: impl FnMut(InstanceSlot, cgmath::Matrix4<f32>),
+    ) {
+        for index in 0..self.slots.len() {
+            let Some(node) = &self.slots[index].node else {
+                continue;
+            };
+            let parent = node.parent;
+            let parent_moved = parent.is_some_and(|parent| self.node(parent).moved);
+            let blending = node.previous != node.local;
+            if !(node.dirty || blending || parent_moved) {
+                self.slots[index].node.as_mut().unwrap().moved = false;
+                continue;
+            }
+
+            let local = if blending {
+                node.previous.lerp(node.local, alpha)
+            } else {
+                node.local
+            };
+            let world = match parent {
+                Some(parent) => self.node(parent).world * local.to_matrix(),
+                None => local.to_matrix(),
+            };
+
+            let node = self.slots[index].node.as_mut().unwrap();
+            node.world = world;
+            node.dirty = false;
+            node.moved = true;
+            if let Some(slot) = node.instance {
+                place(slot, world);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{One, SquareMatrix};
+
+    fn at(x: f32) -> Transform {
+        Transform {
+            translation: cgmath::Vector3::new(x, 0.0, 0.0),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn slot(index: usize) -> InstanceSlot {
+        InstanceSlot::Animated { model: 0, index }
+    }
+
+    /// Stands in for an instance buffer, holding each instance's world
+    /// matrix.
+    fn place_into(
+        buffer: &mut [cgmath::Matrix4<f32>],
+    ) -> impl FnMut(InstanceSlot, cgmath::Matrix4<f32>) + '_ {
+        |slot, world| buffer[slot.index()] = world
+    }
+
+    #[test]
+    fn moving_a_parent_moves_its_children() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add(None, None, at(1.0), Some(slot(0)));
+        graph.add(Some(parent), None, at(2.0), Some(slot(1)));
+        let mut buffer = vec![cgmath::Matrix4::identity(); 2];
+        graph.update(1.0, place_into(&mut buffer));
+        assert_eq!(buffer[1], at(3.0).to_matrix());
+
+        graph.begin_tick();
+        graph.set_local(parent, at(5.0));
+        graph.update(1.0, place_into(&mut buffer));
+        assert_eq!(buffer, [at(5.0).to_matrix(), at(7.0).to_matrix()]);
+
+        // Once the blend has settled nothing is placed again.
+        graph.begin_tick();
+        graph.update(1.0, |_, _| {});
+        graph.update(1.0, |slot, _| panic!("{:?} placed again", slot));
+    }
+
+    #[test]
+    fn moves_are_blended_across_the_tick() {
+        let mut graph = SceneGraph::new();
+        let node = graph.add(None, None, at(0.0), Some(slot(0)));
+        let mut buffer = vec![cgmath::Matrix4::identity(); 1];
+        graph.update(1.0, place_into(&mut buffer));
+
+        graph.begin_tick();
+        graph.set_local(node, at(4.0));
+        graph.update(0.25, place_into(&mut buffer));
+        assert_eq!(buffer[0], at(1.0).to_matrix());
+        graph.update(0.75, place_into(&mut buffer));
+        assert_eq!(buffer[0], at(3.0).to_matrix());
+        assert_eq!(graph.current_world(node), at(4.0).to_matrix());
+
+        // The next tick starts from where the node ended up.
+        graph.begin_tick();
+        graph.update(0.0, place_into(&mut buffer));
+        assert_eq!(buffer[0], at(4.0).to_matrix());
+    }
+
+    #[test]
+    fn current_instances_ignore_blending_and_pending_updates() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add(None, None, at(1.0), None);
+        graph.add(Some(parent), None, at(2.0), Some(slot(0)));
+        graph.update(1.0, |_, _| {});
+
+        graph.begin_tick();
+        graph.set_local(parent, at(5.0));
+        graph.update(0.5, |_, _| {});
+        assert_eq!(graph.current_instances(), [(slot(0), at(7.0).to_matrix())]);
+    }
+
+    #[test]
+    fn removing_a_node_repoints_the_node_placing_the_last_instance() {
+        let mut graph = SceneGraph::new();
+        let nodes = (0..3)
+            .map(|index| graph.add(None, None, at(index as f32), Some(slot(index))))
+            .collect::<Vec<_>>();
+        let mut buffer = vec![cgmath::Matrix4::identity(); 3];
+        graph.update(1.0, place_into(&mut buffer));
+
+        graph.remove(nodes[0], |slot| {
+            buffer.swap_remove(slot.index());
+            buffer.len()
+        });
+        assert_eq!(buffer, [at(2.0).to_matrix(), at(1.0).to_matrix()]);
+
+        // The last node now writes where the removed one was.
+        graph.set_local(nodes[2], at(5.0));
+        graph.update(1.0, place_into(&mut buffer));
+        assert_eq!(buffer, [at(5.0).to_matrix(), at(1.0).to_matrix()]);
+
+        // And is found again when its own instance goes.
+        graph.remove(nodes[2], |slot| {
+            assert_eq!(slot, self::slot(0));
+            buffer.swap_remove(slot.index());
+            buffer.len()
+        });
+        graph.set_local(nodes[1], at(6.0));
+        graph.update(1.0, place_into(&mut buffer));
+        assert_eq!(buffer, [at(6.0).to_matrix()]);
+    }
+
+    #[test]
+    fn removing_the_last_instance_moves_nothing() {
+        let mut graph = SceneGraph::new();
+        let first = graph.add(None, None, at(0.0), Some(slot(0)));
+        let last = graph.add(None, None, at(1.0), Some(slot(1)));
+        let mut buffer = vec![cgmath::Matrix4::identity(); 2];
+
+        graph.remove(last, |slot| {
+            buffer.swap_remove(slot.index());
+            buffer.len()
+        });
+        graph.set_local(first, at(3.0));
+        graph.update(1.0, place_into(&mut buffer));
+        assert_eq!(buffer, [at(3.0).to_matrix()]);
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_children() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add(None, None, at(0.0), None);
+        let parent = graph.add(Some(root), Some("parent"), at(1.0), Some(slot(0)));
+        graph.add(Some(parent), None, at(2.0), Some(slot(1)));
+        let mut buffer = vec![cgmath::Matrix4::<f32>::identity(); 2];
+
+        graph.remove(parent, |slot| {
+            buffer.swap_remove(slot.index());
+            buffer.len()
+        });
+        assert!(buffer.is_empty());
+        assert!(graph.children(root).is_empty());
+        assert_eq!(graph.find("parent"), None);
+    }
+
+    #[test]
+    fn removed_slots_are_reused_after_the_parent() {
+        let mut graph = SceneGraph::new();
+        let first = graph.add(None, None, at(0.0), None);
+        let parent = graph.add(None, None, at(1.0), None);
+        graph.remove(first, |_| 0);
+
+        // The free slot is ahead of the parent, so the child can't take it.
+        let child = graph.add(Some(parent), None, at(2.0), Some(slot(0)));
+        assert_eq!(graph.slots.len(), 3);
+        let mut buffer = vec![cgmath::Matrix4::identity(); 1];
+        graph.update(1.0, place_into(&mut buffer));
+        assert_eq!(buffer[0], at(3.0).to_matrix());
+
+        // A root can, and the stale id no longer reaches it.
+        let root = graph.add(None, None, at(4.0), None);
+        assert_eq!(graph.slots.len(), 3);
+        assert_ne!(root, first);
+        assert_eq!(graph.parent(child), Some(parent));
+    }
+
+    #[test]
+    #[should_panic(expected = "has been removed")]
+    fn removed_nodes_cannot_be_used() {
+        let mut graph = SceneGraph::new();
+        let node = graph.add(None, None, at(0.0), None);
+        graph.remove(node, |_| panic!("nothing to remove"));
+        graph.local(node);
+    }
+}
@@ -1,12 +1,40 @@
 struct CameraUniform {
+    view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+const MAX_POINT_LIGHTS: u32 = 4u;
+const SPECULAR_STRENGTH: f32 = 0.5;
+
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+}
+
+struct LightUniform {
+    sun_view_proj: mat4x4<f32>,
+    ambient: vec3<f32>,
+    num_point_lights: u32,
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    points: array<PointLight, MAX_POINT_LIGHTS>,
+}
+@group(2) @binding(0)
+var<uniform> light: LightUniform;
+@group(2) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
 }
 
 struct InstanceInput {
@@ -14,11 +42,20 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) tint: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+    @location(5) tint: vec4<f32>,
 }
 
 @vertex
@@ -26,16 +63,40 @@ fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
+    return transform_vertex(model, instance);
+}
+
+fn transform_vertex(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    // Tangents lie along the surface, so they stretch with it; only normals
+    // need the inverse transpose.
+    let tangent_matrix = mat3x3<f32>(
+        model_matrix[0].xyz,
+        model_matrix[1].xyz,
+        model_matrix[2].xyz,
+    );
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
 
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = tangent_matrix * model.tangent;
+    out.world_bitangent = tangent_matrix * model.bitangent;
+    out.tint = instance.tint;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
@@ -44,8 +105,106 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+
+struct Surface {
+    normal: vec3<f32>,
+    diffuse_color: vec3<f32>,
+    specular_color: vec3<f32>,
+    shininess: f32,
+}
+
+// Diffuse and specular contribution of one light arriving along `light_dir`.
+fn blinn_phong(
+    light_dir: vec3<f32>,
+    light_color: vec3<f32>,
+    surface: Surface,
+    view_dir: vec3<f32>,
+) -> vec3<f32> {
+    let diffuse = max(dot(surface.normal, light_dir), 0.0) * surface.diffuse_color;
+
+    let half_dir = normalize(view_dir + light_dir);
+    let specular = pow(max(dot(surface.normal, half_dir), 0.0), surface.shininess)
+        * surface.specular_color;
+
+    return (diffuse + specular) * light_color;
+}
+
+// Fraction of the sun's light reaching `world_position`, filtered over a
+// 3x3 block of shadow map texels.
+fn sun_shadow(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    // Nudge the lookup along the normal to avoid acne on grazing surfaces.
+    let offset_position = world_position + normal * 0.02;
+    let light_clip = light.sun_view_proj * vec4<f32>(offset_position, 1.0);
+    let light_ndc = light_clip.xyz / light_clip.w;
+    let uv = light_ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || light_ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    var visibility = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let sample_uv = uv + vec2<f32>(f32(x), f32(y)) * texel_size;
+            visibility += textureSampleCompareLevel(t_shadow, s_shadow, sample_uv, light_ndc.z);
+        }
+    }
+    return visibility / 9.0;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
+
+    let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let normal = normalize(tangent_matrix * tangent_normal);
+    let view_dir = normalize(camera.view_position.xyz - in.world_position);
+
+    let metallic_roughness = textureSample(
+        t_metallic_roughness,
+        s_metallic_roughness,
+        in.tex_coords,
+    );
+    let roughness = metallic_roughness.g;
+    let metallic = metallic_roughness.b;
+    // Map roughness onto a Blinn-Phong exponent the same way the GGX
+    // distribution relates to it.
+    let alpha = max(roughness * roughness, 0.01);
+
+    var surface: Surface;
+    surface.normal = normal;
+    surface.diffuse_color = object_color.rgb * (1.0 - metallic);
+    surface.specular_color = mix(vec3<f32>(SPECULAR_STRENGTH), object_color.rgb, metallic);
+    surface.shininess = 2.0 / (alpha * alpha) - 2.0;
+
+    var color = light.ambient * object_color.rgb;
+    let shadow = sun_shadow(in.world_position, normalize(in.world_normal));
+    color += blinn_phong(-light.sun_direction, light.sun_color, surface, view_dir) * shadow;
+
+    for (var i = 0u; i < light.num_point_lights; i += 1u) {
+        let point = light.points[i];
+        let to_light = point.position - in.world_position;
+        let distance = length(to_light);
+        // Smooth window so the light fades out completely at `range`.
+        let falloff = clamp(1.0 - pow(distance / point.range, 4.0), 0.0, 1.0);
+        let attenuation = falloff * falloff / (distance * distance + 1.0);
+        color += blinn_phong(to_light / distance, point.color, surface, view_dir) * attenuation;
+    }
+
+    return vec4<f32>(color, object_color.a);
+}
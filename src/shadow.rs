@@ -0,0 +1,181 @@
+use crate::instance::InstanceRaw;
+use crate::model::{self, Vertex};
+use crate::texture::{self, DepthMode, Texture};
+
+pub struct ShadowMap {
+    pub texture: Texture,
+    pipeline: wgpu::RenderPipeline,
+    /// For skinned meshes, on devices that can skin them.
+    skinned_pipeline: Option<wgpu::RenderPipeline>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub const SIZE: u32 = 2048;
+
+    /// `light_buffer` must hold a `LightUniform`; only its `sun_view_proj` is
+    /// read by the shadow pass. Skinned meshes cast shadows too when given
+    /// the layout of their joint matrices.
+    pub fn new(
+        device: &wgpu::Device,
+        light_buffer: &wgpu::Buffer,
+        joint_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+    ) -> Self {
+        let texture = Texture::create_sized_depth_texture(
+            device,
+            Self::SIZE,
+            Self::SIZE,
+            DepthMode::Standard,
+            "shadow_map",
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            }],
+            label: Some("shadow_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shadow.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            "vs_main",
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            "Shadow Pipeline",
+        );
+
+        let skinned_pipeline = joint_bind_group_layout.map(|joint_bind_group_layout| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Skinned Shadow Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout, joint_bind_group_layout],
+                immediate_size: 0,
+            });
+            create_pipeline(
+                device,
+                &layout,
+                &shader,
+                "vs_skinned",
+                &[model::SkinnedVertex::desc(), InstanceRaw::desc()],
+                "Skinned Shadow Pipeline",
+            )
+        });
+
+        Self {
+            texture,
+            pipeline,
+            skinned_pipeline,
+            bind_group,
+        }
+    }
+
+    /// Starts a depth-only pass into the shadow map with the pipeline and
+    /// light bound. Callers bind their instance buffer in slot 1 and draw
+    /// with `DrawShadow`.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass
+    }
+
+    /// Switches a pass from `begin_pass` over to skinned meshes, drawn with
+    /// `DrawShadow::draw_skinned_model_shadow_instanced`. Returns false, and
+    /// leaves the pass alone, on devices that can't skin.
+    pub fn use_skinned_pipeline(&self, render_pass: &mut wgpu::RenderPass) -> bool {
+        match &self.skinned_pipeline {
+            Some(pipeline) => {
+                render_pass.set_pipeline(pipeline);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    buffers: &[wgpu::VertexBufferLayout],
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(entry_point),
+            buffers,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            // Slope-scaled bias keeps surfaces from shadowing themselves.
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview_mask: None,
+        cache: None,
+    })
+}
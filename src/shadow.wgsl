@@ -0,0 +1,56 @@
+struct LightUniform {
+    sun_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> light: LightUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return light.sun_view_proj * model_matrix(instance) * vec4<f32>(model.position, 1.0);
+}
+
+// Skinned meshes, posed by the same joint matrices as in skinning.wgsl.
+@group(1) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+struct SkinInput {
+    @location(13) joints: vec4<u32>,
+    @location(14) weights: vec4<f32>,
+}
+
+@vertex
+fn vs_skinned(
+    model: VertexInput,
+    skin: SkinInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let skin_matrix = joint_matrices[skin.joints.x] * skin.weights.x
+        + joint_matrices[skin.joints.y] * skin.weights.y
+        + joint_matrices[skin.joints.z] * skin.weights.z
+        + joint_matrices[skin.joints.w] * skin.weights.w;
+    let posed = skin_matrix * vec4<f32>(model.position, 1.0);
+    return light.sun_view_proj * model_matrix(instance) * posed;
+}
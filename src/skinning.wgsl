@@ -0,0 +1,31 @@
+// Skinned vertex entry point. Appended to shader.wgsl at pipeline creation
+// so it shares the fragment stage and bind groups 0-2.
+
+@group(3) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+struct SkinInput {
+    @location(13) joints: vec4<u32>,
+    @location(14) weights: vec4<f32>,
+}
+
+@vertex
+fn vs_skinned(
+    model: VertexInput,
+    skin: SkinInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let skin_matrix = joint_matrices[skin.joints.x] * skin.weights.x
+        + joint_matrices[skin.joints.y] * skin.weights.y
+        + joint_matrices[skin.joints.z] * skin.weights.z
+        + joint_matrices[skin.joints.w] * skin.weights.w;
+    let skin_linear = mat3x3<f32>(skin_matrix[0].xyz, skin_matrix[1].xyz, skin_matrix[2].xyz);
+
+    var posed: VertexInput;
+    posed.position = (skin_matrix * vec4<f32>(model.position, 1.0)).xyz;
+    posed.tex_coords = model.tex_coords;
+    posed.normal = skin_linear * model.normal;
+    posed.tangent = skin_linear * model.tangent;
+    posed.bitangent = skin_linear * model.bitangent;
+    return transform_vertex(posed, instance);
+}
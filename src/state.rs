@@ -1,59 +1,120 @@
-use cgmath::Zero;
-use cgmath::prelude::*;
+use anyhow::Context;
+use cgmath::{EuclideanSpace, One, Rotation3};
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
-use winit::{event_loop::ActiveEventLoop, keyboard::KeyCode, window::Window};
+use winit::{event::MouseScrollDelta, window::Window};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use crate::instance::InstanceRaw;
-use crate::model::{self, DrawModel, Vertex};
+use crate::animation::{AnimatedModel, Transform};
+use crate::bloom::{Bloom, BloomControl, BloomOutput};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::capture;
+use crate::game_loop::{FixedTimestep, Spin, TickContext, TickSystem};
+use crate::gamepad::{self, GamepadBackend};
+use crate::hdr::HdrPipeline;
+use crate::input::{self, Action, Binding, Input, InputEvent, InputMap};
+use crate::instance::{Instance, InstanceRaw};
+use crate::light::{LightUniform, Lights};
+use crate::model::{self, DrawModel, DrawShadow, Vertex};
+use crate::recording::InputLog;
+use crate::registry::{ModelId, ModelRegistry};
 use crate::resources;
+use crate::scene::{self, Scene};
+use crate::scene_graph::{InstanceSlot, NodeId, SceneGraph};
+use crate::shadow::ShadowMap;
 use crate::texture::Texture;
 use crate::{
-    camera::{Camera, CameraController, CameraUniform},
-    instance::Instance,
+    camera::{
+        Camera, CameraController, CameraMode, CameraUniform, FollowController, OrbitController,
+        Projection,
+    },
     texture,
 };
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-const SPACE_BETWEEN: f32 = 3.0;
+/// Simulation rate, independent of the frame rate.
+const TICKS_PER_SECOND: f32 = 60.0;
+/// Ticks between saves of an input recording in progress.
+const RECORDING_SAVE_TICKS: u64 = 5 * TICKS_PER_SECOND as u64;
+/// How far in front of the camera `place_prop` drops a prop.
+const PROP_DISTANCE: f32 = 4.0;
+/// How far `turn_prop` turns a prop each press.
+const PROP_TURN: cgmath::Deg<f32> = cgmath::Deg(45.0);
+/// Stops of exposure per press of the exposure keys.
+const EXPOSURE_STEP: f32 = 0.5;
 
 pub(crate) struct State {
-    surface: wgpu::Surface<'static>,
+    /// `None` when headless, where frames are only drawn by `capture`.
+    surface: Option<wgpu::Surface<'static>>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     is_surface_configured: bool,
     mouse_pos: (f64, f64),
+    last_cursor_position: Option<(f64, f64)>,
+    input: Input,
+    gamepad: Option<Box<dyn GamepadBackend>>,
+    quit_requested: bool,
+    /// Save the next frame `render` draws.
+    screenshot_requested: bool,
+    /// Puts screenshot frames on surfaces that can't be copied from.
+    #[cfg(not(target_arch = "wasm32"))]
+    screenshot_blit: Option<capture::Blit>,
     render_pipeline: wgpu::RenderPipeline,
+    skinned_pipeline: Option<wgpu::RenderPipeline>,
     camera: Camera,
+    /// The camera as of the previous tick, for blending towards `camera`.
+    previous_camera: Camera,
+    /// The projection `P` toggles to.
+    other_projection: Projection,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     camera_controller: CameraController,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
+    orbit_controller: OrbitController,
+    follow_controller: FollowController,
+    /// Node the follow camera trails, if the scene names one.
+    follow_target: Option<NodeId>,
+    camera_mode: CameraMode,
+    lights: Lights,
+    light_uniform: LightUniform,
+    light_buffer: wgpu::Buffer,
+    light_bind_group: wgpu::BindGroup,
+    shadow_map: ShadowMap,
     depth_texture: Texture,
-    obj_model: model::Model,
+    hdr: HdrPipeline,
+    /// `None` where the adapter can't run it.
+    bloom: Option<Bloom>,
+    models: ModelRegistry,
+    scene_graph: SceneGraph,
+    animated_models: Vec<AnimatedModel>,
+    /// Model that `place_prop` drops copies of: the scene's first static one.
+    prop_model: Option<ModelId>,
+    /// Scene graph nodes of the placed props, newest last.
+    props: Vec<NodeId>,
     last_frame_time: std::time::Instant,
-    pub window: Arc<Window>,
+    timestep: FixedTimestep,
+    /// Simulation ticks run so far.
+    ticks: u64,
+    input_log: InputLog,
+    systems: Vec<Box<dyn TickSystem>>,
 }
 
 impl State {
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<State> {
+    pub async fn new(
+        window: Arc<Window>,
+        scene_path: &str,
+        input_log: InputLog,
+    ) -> anyhow::Result<State> {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
-            ..Default::default()
+        let instance = create_instance(if cfg!(target_arch = "wasm32") {
+            wgpu::Backends::GL
+        } else {
+            wgpu::Backends::PRIMARY
         });
-
-        let surface = instance.create_surface(window.clone()).unwrap();
+        let surface = instance.create_surface(window).unwrap();
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -63,21 +124,6 @@ impl State {
             })
             .await?;
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-            })
-            .await?;
-
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
@@ -85,8 +131,11 @@ impl State {
             .find(|f| f.is_srgb())
             .copied()
             .unwrap_or(surface_caps.formats[0]);
+        // Lets screenshots copy straight from the frame where that's allowed.
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -96,6 +145,88 @@ impl State {
             desired_maximum_frame_latency: 2,
         };
 
+        Self::with_adapter(adapter, Some(surface), config, scene_path, input_log).await
+    }
+
+    /// A `State` without a window, for rendering with `capture`. Falls back
+    /// to a software adapter such as llvmpipe or lavapipe when there's no GPU.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        scene_path: &str,
+        input_log: InputLog,
+    ) -> anyhow::Result<State> {
+        // Software rasterizers often only come as OpenGL, like Mesa's llvmpipe.
+        // WGPU_BACKEND picks one explicitly.
+        let instance = create_instance(
+            wgpu::Backends::from_env().unwrap_or(wgpu::Backends::PRIMARY | wgpu::Backends::GL),
+        );
+        let mut options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        };
+        let adapter = match instance.request_adapter(&options).await {
+            Ok(adapter) => adapter,
+            Err(_) => {
+                options.force_fallback_adapter = true;
+                instance
+                    .request_adapter(&options)
+                    .await
+                    .context("No GPU or software adapter available")?
+            }
+        };
+        log::info!("Rendering headless on {:?}", adapter.get_info().name);
+
+        // Not presented anywhere; it only fixes the color format and size.
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+
+        let mut state = Self::with_adapter(adapter, None, config, scene_path, input_log).await?;
+        state.gamepad = None;
+        Ok(state)
+    }
+
+    async fn with_adapter(
+        adapter: wgpu::Adapter,
+        surface: Option<wgpu::Surface<'static>>,
+        config: wgpu::SurfaceConfiguration,
+        scene_path: &str,
+        input_log: InputLog,
+    ) -> anyhow::Result<State> {
+        let scene = Scene::load(scene_path).await?;
+        let map = InputMap::load(input::DEFAULT_INPUT_MAP)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("{}; using the built-in bindings", e);
+                InputMap::default()
+            });
+        let input = Input::new(map);
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                experimental_features: wgpu::ExperimentalFeatures::disabled(),
+                required_limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+                memory_hints: Default::default(),
+                trace: wgpu::Trace::Off,
+            })
+            .await?;
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -115,24 +246,71 @@ impl State {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let camera = Camera {
-            eye: (0.0, 1.0, 2.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
-            up: cgmath::Vector3::unit_y(),
-            aspect: config.width as f32 / config.height as f32,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 1000.0,
-        };
+        let (camera, other_projection) = scene
+            .camera
+            .to_camera(config.width as f32 / config.height as f32);
+
+        let depth_texture = texture::Texture::create_depth_texture(
+            &device,
+            &config,
+            camera.depth_mode,
+            "depth_texture",
+        );
+        let hdr = HdrPipeline::new(
+            &device,
+            &adapter,
+            config.width,
+            config.height,
+            config.format,
+            scene.tonemapping,
+        );
+        let bloom = Bloom::new(
+            &device,
+            &adapter,
+            &hdr,
+            config.width,
+            config.height,
+            scene.bloom,
+        );
 
         let camera_controller = CameraController::new(8.0, 0.1);
+        let orbit_controller = OrbitController::new(5.0, 0.005, 0.1);
 
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
@@ -147,7 +325,7 @@ impl State {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -167,6 +345,98 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
+        let lights = scene.lights.to_lights();
+
+        let mut light_uniform = LightUniform::new();
+        light_uniform.update_lights(&lights);
+
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[light_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                ],
+                label: Some("light_bind_group_layout"),
+            });
+
+        // Skinning reads joint matrices from a storage buffer in the vertex
+        // stage, which WebGL2 and other downlevel targets can't do.
+        let supports_skinning = device.limits().max_storage_buffers_per_shader_stage > 0
+            && adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::VERTEX_STORAGE);
+
+        let joint_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("joint_bind_group_layout"),
+            });
+
+        let shadow_map = ShadowMap::new(
+            &device,
+            &light_buffer,
+            supports_skinning.then_some(&joint_bind_group_layout),
+        );
+
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&shadow_map.texture.sampler),
+                },
+            ],
+            label: Some("light_bind_group"),
+        });
+
         //let modes = &surface_caps.present_modes;
 
         let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
@@ -174,174 +444,708 @@ impl State {
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                ],
                 immediate_size: 0,
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview_mask: None,
-            cache: None,
-        });
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            hdr.format(),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            &shader,
+            "vs_main",
+            camera.depth_mode,
+            "Render Pipeline",
+        );
 
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+        let skinned_pipeline = supports_skinning.then(|| {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Skinning Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(include_str!("shader.wgsl"), include_str!("skinning.wgsl")).into(),
+                ),
+            });
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Skinned Pipeline Layout"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    &joint_bind_group_layout,
+                ],
+                immediate_size: 0,
+            });
+            create_render_pipeline(
+                &device,
+                &layout,
+                hdr.format(),
+                &[model::SkinnedVertex::desc(), InstanceRaw::desc()],
+                &shader,
+                "vs_skinned",
+                camera.depth_mode,
+                "Skinned Pipeline",
+            )
+        });
 
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
+        let mut models = ModelRegistry::new();
+        let mut animated_models = Vec::new();
+        let mut prop_model = None;
+        let mut placements = Vec::new();
+        for desc in &scene.models {
+            let model_instances = desc
+                .instances
+                .iter()
+                .map(|instance| instance.to_instance())
+                .collect::<Vec<_>>();
 
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
+            if desc.animated {
+                if !supports_skinning {
+                    log::warn!(
+                        "Skipping {:?}: skinned meshes are not supported on this device",
+                        desc.path
+                    );
+                    placements.extend(desc.instances.iter().map(|instance| (None, instance)));
+                    continue;
+                }
+                let model = resources::load_skinned_gltf(
+                    &desc.path,
+                    &device,
+                    &queue,
+                    &texture_bind_group_layout,
+                )
+                .await?;
+                let slot = animated_models.len();
+                placements.extend(desc.instances.iter().enumerate().map(|(index, instance)| {
+                    let slot = InstanceSlot::Animated { model: slot, index };
+                    (Some(slot), instance)
+                }));
+                animated_models.push(AnimatedModel::new(
+                    &device,
+                    model,
+                    model_instances,
+                    &joint_bind_group_layout,
+                ));
+            } else {
+                let model =
+                    resources::load_model(&desc.path, &device, &queue, &texture_bind_group_layout)
+                        .await?;
+                let id = models.add(&device, model, model_instances);
+                prop_model.get_or_insert(id);
+                placements.extend(desc.instances.iter().enumerate().map(|(index, instance)| {
+                    let slot = InstanceSlot::Static { model: id, index };
+                    (Some(slot), instance)
+                }));
+            }
+        }
 
-                    Instance { position, rotation }
-                })
-            })
+        let (scene_graph, nodes) = scene::build_scene_graph(&placements)?;
+        let spinning = placements
+            .iter()
+            .zip(nodes)
+            .filter(|((_, desc), _)| desc.spin != 0.0)
+            .map(|((_, desc), node)| (node, desc.spin))
             .collect::<Vec<_>>();
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let obj_model =
-            resources::load_model("cube/cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
+        let (follow_controller, follow_target) = match &scene.camera.follow {
+            Some(follow) => {
+                let target = scene_graph
+                    .find(&follow.target)
+                    .with_context(|| format!("Unknown follow camera target {:?}", follow.target))?;
+                let controller = FollowController::new(
+                    follow.offset.into(),
+                    follow.look_height,
+                    follow.stiffness,
+                    0.1,
+                );
+                (controller, Some(target))
+            }
+            None => (
+                FollowController::new((0.0, 2.0, 6.0).into(), 1.0, 8.0, 0.1),
+                None,
+            ),
+        };
 
-        Ok(Self {
+        // There's nothing to configure without a surface.
+        let is_surface_configured = surface.is_none();
+        #[cfg(not(target_arch = "wasm32"))]
+        let screenshot_blit = (surface.is_some()
+            && !config.usage.contains(wgpu::TextureUsages::COPY_SRC))
+        .then(|| capture::Blit::new(&device, config.format));
+        let mut state = Self {
             surface,
             device,
             queue,
             config,
             mouse_pos: (0.0, 0.0),
-            is_surface_configured: false,
+            last_cursor_position: None,
+            input,
+            gamepad: gamepad::default_backend(),
+            quit_requested: false,
+            screenshot_requested: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            screenshot_blit,
+            is_surface_configured,
             render_pipeline,
+            skinned_pipeline,
+            previous_camera: camera,
             camera,
+            other_projection,
             camera_uniform,
             camera_buffer,
             camera_bind_group,
             camera_controller,
-            instances,
-            instance_buffer,
+            orbit_controller,
+            follow_controller,
+            follow_target,
+            camera_mode: CameraMode::Fly,
+            lights,
+            light_uniform,
+            light_buffer,
+            light_bind_group,
+            shadow_map,
             depth_texture,
-            obj_model,
-            window,
+            hdr,
+            bloom,
+            models,
+            scene_graph,
+            animated_models,
+            prop_model,
+            props: Vec::new(),
             last_frame_time: std::time::Instant::now(),
-        })
+            timestep: FixedTimestep::new(TICKS_PER_SECOND),
+            ticks: 0,
+            input_log,
+            systems: Vec::new(),
+        };
+        if !spinning.is_empty() {
+            state.add_system(Spin { nodes: spinning });
+        }
+        Ok(state)
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
             self.is_surface_configured = true;
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.depth_texture = texture::Texture::create_depth_texture(
+                &self.device,
+                &self.config,
+                self.camera.depth_mode,
+                "depth_texture",
+            );
+            self.hdr.resize(&self.device, width, height);
+            if let Some(bloom) = &mut self.bloom {
+                bloom.resize(&self.device, &self.hdr, width, height);
+            }
 
-            self.camera.aspect = width as f32/height as f32
+            self.camera.projection.resize(width, height);
+            self.other_projection.resize(width, height);
         }
     }
 
-    pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        if code == KeyCode::Escape && is_pressed {
-            event_loop.exit();
-        } else {
-            self.camera_controller.handle_key(code, is_pressed);
+    /// Entry point for every live input event. While replaying, live events
+    /// are dropped and the recording's are fed in by `tick` instead.
+    pub fn handle_input(&mut self, event: InputEvent) {
+        if self.input_log.live_event(self.ticks, event) {
+            self.apply_input(event);
+        }
+    }
+
+    fn apply_input(&mut self, event: InputEvent) {
+        let started = match event {
+            InputEvent::Key { code, pressed } => {
+                self.input.handle_binding(Binding::Key(code), pressed)
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                self.input.handle_binding(Binding::Mouse(button), pressed)
+            }
+            InputEvent::CursorMoved { x, y } => {
+                self.handle_mouse_moved(x, y);
+                Vec::new()
+            }
+            InputEvent::MouseMotion { delta_x, delta_y } => {
+                self.handle_mouse_motion(delta_x, delta_y);
+                Vec::new()
+            }
+            InputEvent::Scroll(delta) => {
+                self.handle_mouse_scroll(delta);
+                Vec::new()
+            }
+            InputEvent::Gamepad(pad, event) => self.input.handle_gamepad(pad, event),
+        };
+        self.start_actions(started);
+    }
+
+    /// Writes out the input recording, if one is being made.
+    pub fn finish_input_log(&mut self) -> anyhow::Result<()> {
+        self.input_log.finish()
+    }
+
+    fn start_actions(&mut self, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Quit => self.quit_requested = true,
+                Action::ToggleCameraMode => self.toggle_camera_mode(),
+                Action::ToggleProjection => {
+                    std::mem::swap(&mut self.camera.projection, &mut self.other_projection)
+                }
+                Action::PlaceProp => self.place_prop(),
+                Action::TurnProp => self.turn_prop(),
+                Action::RemoveProp => self.remove_prop(),
+                Action::Rebind => self.input.start_rebinding(),
+                Action::CycleTonemapper => {
+                    let tonemapping = &mut self.hdr.tonemapping;
+                    tonemapping.tonemapper = tonemapping.tonemapper.next();
+                    log::info!("Tonemapper: {:?}", tonemapping.tonemapper);
+                }
+                Action::ExposureUp | Action::ExposureDown => {
+                    let step = if action == Action::ExposureUp {
+                        EXPOSURE_STEP
+                    } else {
+                        -EXPOSURE_STEP
+                    };
+                    self.hdr.tonemapping.exposure += step;
+                    log::info!("Exposure: {:+.1} EV", self.hdr.tonemapping.exposure);
+                }
+                Action::ToggleBloom => {
+                    if let Some(bloom) = &mut self.bloom {
+                        bloom.settings.enabled = !bloom.settings.enabled;
+                        log::info!(
+                            "Bloom: {}",
+                            if bloom.settings.enabled { "on" } else { "off" }
+                        );
+                    }
+                }
+                Action::BloomIntensityUp
+                | Action::BloomIntensityDown
+                | Action::BloomThresholdUp
+                | Action::BloomThresholdDown
+                | Action::BloomRadiusUp
+                | Action::BloomRadiusDown => {
+                    if let Some(bloom) = &mut self.bloom {
+                        let (control, up) = match action {
+                            Action::BloomIntensityUp => (BloomControl::Intensity, true),
+                            Action::BloomIntensityDown => (BloomControl::Intensity, false),
+                            Action::BloomThresholdUp => (BloomControl::Threshold, true),
+                            Action::BloomThresholdDown => (BloomControl::Threshold, false),
+                            Action::BloomRadiusUp => (BloomControl::Radius, true),
+                            _ => (BloomControl::Radius, false),
+                        };
+                        bloom.settings.step(control, up);
+                        let settings = &bloom.settings;
+                        log::info!(
+                            "Bloom intensity: {:.2}, threshold: {:.2}, radius: {:.4}",
+                            settings.intensity,
+                            settings.threshold,
+                            settings.radius
+                        );
+                    }
+                }
+                Action::Screenshot => self.screenshot_requested = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Drops a copy of the prop model a little way in front of the camera.
+    fn place_prop(&mut self) {
+        let Some(model) = self.prop_model else {
+            return;
+        };
+        let transform = Transform {
+            translation: self.camera.position.to_vec() + self.camera.forward() * PROP_DISTANCE,
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        };
+        let index = self.models.get_mut(model).instances.push(Instance {
+            position: transform.translation,
+            rotation: transform.rotation,
+            scale: transform.scale,
+            tint: [1.0; 4],
+        });
+        let slot = InstanceSlot::Static { model, index };
+        let node = self.scene_graph.add(None, None, transform, Some(slot));
+        self.props.push(node);
+    }
+
+    /// Turns the newest prop about the vertical axis.
+    fn turn_prop(&mut self) {
+        if let Some(&node) = self.props.last() {
+            let mut local = *self.scene_graph.local(node);
+            local.rotation = cgmath::Quaternion::from_angle_y(PROP_TURN) * local.rotation;
+            self.scene_graph.set_local(node, local);
+        }
+    }
+
+    /// Removes the newest prop.
+    fn remove_prop(&mut self) {
+        if let Some(node) = self.props.pop() {
+            self.remove_node(node);
+        }
+    }
+
+    /// Removes `node` and the instances it places, keeping the scene graph
+    /// pointed at the right slots as the buffers fill the gaps.
+    fn remove_node(&mut self, node: NodeId) {
+        let models = &mut self.models;
+        let animated_models = &mut self.animated_models;
+        self.scene_graph.remove(node, |slot| {
+            let instances = match slot {
+                InstanceSlot::Static { model, .. } => &mut models.get_mut(model).instances,
+                InstanceSlot::Animated { model, .. } => &mut animated_models[model].instances,
+            };
+            instances.swap_remove(slot.index());
+            instances.len()
+        });
+    }
+
+    /// Moves the camera to `position`, facing `target`, with no blending
+    /// from where it was.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn place_camera(&mut self, position: cgmath::Point3<f32>, target: cgmath::Point3<f32>) {
+        self.camera.position = position;
+        self.camera.look_at(target);
+        self.previous_camera = self.camera;
+    }
+
+    /// Whether recorded input is still being played back.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replaying(&self) -> bool {
+        self.input_log.is_replaying()
+    }
+
+    /// Set once the quit action fires; the app exits when it sees it.
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    /// Drops a quit request, when the app frees the cursor instead.
+    pub fn cancel_quit(&mut self) {
+        self.quit_requested = false;
+    }
+
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    pub fn camera_mode(&self) -> CameraMode {
+        self.camera_mode
+    }
+
+    fn toggle_camera_mode(&mut self) {
+        self.camera_mode = match self.camera_mode {
+            CameraMode::Fly => {
+                self.orbit_controller.focus_ahead(&self.camera);
+                CameraMode::Orbit
+            }
+            CameraMode::Orbit if self.follow_target.is_some() => {
+                self.follow_controller.reset();
+                CameraMode::Follow
+            }
+            CameraMode::Orbit | CameraMode::Follow => CameraMode::Fly,
+        };
+    }
+
+    /// Cursor position in pixels. Only steers modes that leave the cursor
+    /// free; the others use `handle_mouse_motion`.
+    fn handle_mouse_moved(&mut self, x: f64, y: f64) {
+        if !self.camera_mode.grabs_cursor()
+            && let Some((last_x, last_y)) = self.last_cursor_position
+        {
+            self.input
+                .handle_mouse_moved((x - last_x) as f32, (y - last_y) as f32);
+        }
+        self.last_cursor_position = Some((x, y));
+    }
+
+    /// Raw mouse movement, reported while the cursor is grabbed.
+    fn handle_mouse_motion(&mut self, delta_x: f64, delta_y: f64) {
+        if self.camera_mode.grabs_cursor() {
+            self.input
+                .handle_mouse_moved(delta_x as f32, delta_y as f32);
         }
     }
 
-    pub fn handle_mouse_moved(&mut self, x: f64, y: f64) {
-        let size = self.window.inner_size();
-        let center = (size.width as f64 * 0.5, size.height as f64 * 0.5);
-        self.camera_controller
-            .handle_mouse_moved((x - center.0) as f32, (y - center.1) as f32);
+    fn handle_mouse_scroll(&mut self, delta: MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            // Roughly one line per 50 pixels on touchpads.
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+        };
+        self.input.handle_scroll(lines);
     }
 
+    /// Runs every simulation tick that's due, then places everything for
+    /// drawing, blended between the last two ticks.
     pub fn update(&mut self) {
         let delta = self.last_frame_time.elapsed().as_secs_f32();
-        self.camera_controller
-            .update_camera(&mut self.camera, delta);
-        self.camera_uniform.update_view_proj(&self.camera);
+        self.last_frame_time = std::time::Instant::now();
+        self.advance(delta);
+    }
+
+    /// `update` with a given frame time rather than the wall clock, so
+    /// headless runs come out the same every time.
+    pub fn advance(&mut self, delta: f32) {
+        if let Some(gamepad) = &mut self.gamepad {
+            let events = std::iter::from_fn(|| gamepad.next_event()).collect::<Vec<_>>();
+            for (pad, event) in events {
+                self.handle_input(InputEvent::Gamepad(pad, event));
+            }
+        }
+
+        for _ in 0..self.timestep.advance(delta) {
+            let replaying = self.input_log.is_replaying();
+            for event in self.input_log.due(self.ticks) {
+                self.apply_input(event);
+            }
+            self.tick(self.timestep.tick());
+            if replaying && !self.input_log.is_replaying() {
+                // Whatever the recording left held isn't held by the player.
+                self.input.release_all();
+            }
+        }
+        let alpha = self.timestep.alpha();
+
+        let models = &mut self.models;
+        let animated_models = &mut self.animated_models;
+        self.scene_graph.update(alpha, |slot, world| match slot {
+            InstanceSlot::Static { model, index } => {
+                models
+                    .get_mut(model)
+                    .instances
+                    .set_world_matrix(index, world);
+            }
+            InstanceSlot::Animated { model, index } => {
+                animated_models[model]
+                    .instances
+                    .set_world_matrix(index, world);
+            }
+        });
+
+        for animated_model in &mut self.animated_models {
+            animated_model.sync(&self.device, &self.queue);
+        }
+        self.models.sync(&self.device, &self.queue);
+        self.camera_uniform
+            .update_view_proj(&self.previous_camera.lerp(&self.camera, alpha));
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
-        self.last_frame_time = std::time::Instant::now();
+        self.light_uniform.update_lights(&self.lights);
+        self.queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[self.light_uniform]),
+        );
+        if let Some(bloom) = &self.bloom {
+            bloom.update(&self.queue);
+        }
+        self.hdr.update(&self.queue, self.bloom_output().as_ref());
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
+    /// Advances the simulation by `delta` seconds, always the same.
+    fn tick(&mut self, delta: f32) {
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(RECORDING_SAVE_TICKS)
+            && let Err(e) = self.input_log.save()
+        {
+            log::error!("Unable to save input recording: {}", e);
+        }
+        self.previous_camera = self.camera;
+        self.scene_graph.begin_tick();
 
+        let mut context = TickContext {
+            scene_graph: &mut self.scene_graph,
+            input: &self.input,
+            delta,
+        };
+        for system in &mut self.systems {
+            system.tick(&mut context);
+        }
+        for animated_model in &mut self.animated_models {
+            animated_model.advance(delta);
+        }
+
+        match self.camera_mode {
+            CameraMode::Fly => {
+                self.camera_controller
+                    .update_camera(&mut self.camera, &self.input, delta)
+            }
+            CameraMode::Orbit => {
+                self.orbit_controller
+                    .update_camera(&mut self.camera, &self.input, delta)
+            }
+            CameraMode::Follow => {
+                if let Some(target) = self.follow_target {
+                    // Where everything is this tick, not where it's drawn
+                    // partway through a blend.
+                    let placements = self
+                        .scene_graph
+                        .current_instances()
+                        .into_iter()
+                        .filter_map(|(slot, world)| match slot {
+                            InstanceSlot::Static { model, .. } => Some((model, world)),
+                            InstanceSlot::Animated { .. } => None,
+                        })
+                        .collect::<Vec<_>>();
+                    let models = &self.models;
+                    self.follow_controller.update_camera(
+                        &mut self.camera,
+                        self.scene_graph.current_world(target),
+                        &self.input,
+                        delta,
+                        |ray, max_distance| {
+                            models.raycast(ray, max_distance, placements.iter().copied())
+                        },
+                    );
+                }
+            }
+        }
+
+        self.input.end_tick();
+    }
+
+    /// Adds game logic to run on every simulation tick.
+    pub fn add_system(&mut self, system: impl TickSystem + 'static) {
+        self.systems.push(Box::new(system));
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let Some(surface) = &self.surface else {
+            return Ok(());
+        };
         if !self.is_surface_configured {
             return Ok(());
         }
 
-        let output = self.surface.get_current_texture()?;
+        let output = surface.get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        if std::mem::take(&mut self.screenshot_requested) {
+            self.draw_with_screenshot(&output.texture, &view);
+        } else {
+            self.draw(&view);
+        }
+        output.present();
+
+        Ok(())
+    }
+
+    /// Draws the frame into `frame`, whose view is `view`, and saves it.
+    /// Surfaces that can't be copied from get the frame drawn into a texture
+    /// that can, which is then blitted onto them.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw_with_screenshot(&self, frame: &wgpu::Texture, view: &wgpu::TextureView) {
+        let image = match &self.screenshot_blit {
+            None => {
+                self.draw(view);
+                capture::read_texture(&self.device, &self.queue, frame)
+            }
+            Some(blit) => {
+                let target = self.capture_target();
+                let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+                self.draw(&target_view);
+                let mut encoder =
+                    self.device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("Screenshot Encoder"),
+                        });
+                blit.copy(&self.device, &mut encoder, &target_view, view);
+                self.queue.submit(std::iter::once(encoder.finish()));
+                capture::read_texture(&self.device, &self.queue, &target)
+            }
+        };
+        match image {
+            Ok(image) => capture::save_screenshot(image),
+            Err(e) => log::error!("Unable to take screenshot: {}", e),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn draw_with_screenshot(&self, _frame: &wgpu::Texture, view: &wgpu::TextureView) {
+        log::warn!("Screenshots aren't supported on the web");
+        self.draw(view);
+    }
+
+    /// Draws the current frame into an offscreen texture and reads it back.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture(&self) -> anyhow::Result<image::RgbaImage> {
+        let target = self.capture_target();
+        self.draw(&target.create_view(&wgpu::TextureViewDescriptor::default()));
+        capture::read_texture(&self.device, &self.queue, &target)
+    }
+
+    /// A texture like the surface's, but one that can be copied and blitted
+    /// from.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn capture_target(&self) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture Target"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    }
+
+    fn bloom_output(&self) -> Option<BloomOutput<'_>> {
+        self.bloom.as_ref().and_then(Bloom::output)
+    }
 
+    fn draw(&self, view: &wgpu::TextureView) {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
+        {
+            let mut shadow_pass = self.shadow_map.begin_pass(&mut encoder);
+            for entry in self.models.iter() {
+                shadow_pass.set_vertex_buffer(1, entry.instances.buffer().slice(..));
+                shadow_pass.draw_model_shadow_instanced(&entry.model, entry.instances.range());
+            }
+            if self.shadow_map.use_skinned_pipeline(&mut shadow_pass) {
+                for animated_model in &self.animated_models {
+                    if animated_model.instances.is_empty() {
+                        continue;
+                    }
+                    shadow_pass.set_vertex_buffer(1, animated_model.instances.buffer().slice(..));
+                    shadow_pass.draw_skinned_model_shadow_instanced(
+                        &animated_model.model,
+                        animated_model.instances.range(),
+                        &animated_model.joint_bind_group,
+                    );
+                }
+            }
+        }
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: self.hdr.view(),
                     resolve_target: None,
                     depth_slice: None,
                     ops: wgpu::Operations {
@@ -358,7 +1162,7 @@ impl State {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear(self.camera.depth_mode.clear_value()),
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -368,18 +1172,104 @@ impl State {
                 multiview_mask: None,
             });
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
-            );
+            for entry in self.models.iter() {
+                render_pass.set_vertex_buffer(1, entry.instances.buffer().slice(..));
+                render_pass.draw_model_instanced(
+                    &entry.model,
+                    entry.instances.range(),
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+            }
+
+            if let Some(skinned_pipeline) = &self.skinned_pipeline {
+                render_pass.set_pipeline(skinned_pipeline);
+                for animated_model in &self.animated_models {
+                    if animated_model.instances.is_empty() {
+                        continue;
+                    }
+                    render_pass.set_vertex_buffer(1, animated_model.instances.buffer().slice(..));
+                    render_pass.draw_skinned_model_instanced(
+                        &animated_model.model,
+                        animated_model.instances.range(),
+                        &self.camera_bind_group,
+                        &self.light_bind_group,
+                        &animated_model.joint_bind_group,
+                    );
+                }
+            }
         }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(bloom) = &self.bloom {
+            bloom.render(&mut encoder);
+        }
+        self.hdr
+            .process(&mut encoder, view, self.bloom_output().as_ref());
 
-        Ok(())
+        self.queue.submit(std::iter::once(encoder.finish()));
     }
 }
+
+fn create_instance(backends: wgpu::Backends) -> wgpu::Instance {
+    wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &wgpu::ShaderModule,
+    vertex_entry_point: &str,
+    depth_mode: texture::DepthMode,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(vertex_entry_point),
+            buffers: vertex_layouts,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: depth_mode.compare(),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview_mask: None,
+        cache: None,
+    })
+}
@@ -1,6 +1,34 @@
 use anyhow::*;
 use image::GenericImageView;
 
+/// Which end of the depth range is nearest the camera.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    /// Near is 0 and far is 1.
+    Standard,
+    /// Near is 1 and far is 0. Floating point depth has far more precision
+    /// near 0, so reversing the range spreads it evenly over distance.
+    Reversed,
+}
+
+impl DepthMode {
+    /// The comparison that keeps the nearer fragment.
+    pub fn compare(self) -> wgpu::CompareFunction {
+        match self {
+            DepthMode::Standard => wgpu::CompareFunction::Less,
+            DepthMode::Reversed => wgpu::CompareFunction::Greater,
+        }
+    }
+
+    /// The depth of an empty pixel, as far away as possible.
+    pub fn clear_value(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::Reversed => 0.0,
+        }
+    }
+}
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -14,11 +42,22 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        mode: DepthMode,
+        label: &str,
+    ) -> Self {
+        Self::create_sized_depth_texture(device, config.width, config.height, mode, label)
+    }
+
+    pub fn create_sized_depth_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        mode: DepthMode,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -41,7 +80,10 @@ impl Texture {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::MipmapFilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
+            compare: Some(match mode {
+                DepthMode::Standard => wgpu::CompareFunction::LessEqual,
+                DepthMode::Reversed => wgpu::CompareFunction::GreaterEqual,
+            }),
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             ..Default::default()
@@ -59,16 +101,20 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        is_linear: bool,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), is_linear)
     }
 
+    /// `is_linear` stores the texels without sRGB decoding, for data textures
+    /// such as normal or metallic-roughness maps.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        is_linear: bool,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -84,7 +130,11 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: if is_linear {
+                wgpu::TextureFormat::Rgba8Unorm
+            } else {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            },
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -0,0 +1,190 @@
+//! Renders scenes from fixed camera poses and compares them with the
+//! reference images in `tests/golden/`.
+//!
+//! After a change that's meant to alter the look, regenerate the references
+//! with `GOLDEN_UPDATE=1 cargo test --test golden` and check the new images
+//! in. On a mismatch the render and a diff image are written to
+//! `target/tmp/golden/`.
+
+use std::path::{Path, PathBuf};
+
+use actual_game::headless::HeadlessRenderer;
+use image::{Rgba, RgbaImage};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+/// Color difference (CIE76 ΔE) below which two pixels count as the same.
+/// About 2.3 is just noticeable; a bit more leaves room for GPUs to round
+/// differently.
+const MAX_DELTA_E: f32 = 5.0;
+/// Share of pixels allowed past `MAX_DELTA_E`, for edges that rasterize a
+/// pixel over on some adapters.
+const MAX_DIFFERING: f32 = 0.002;
+
+struct Case {
+    name: &'static str,
+    scene: &'static str,
+    eye: [f32; 3],
+    target: [f32; 3],
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "cube_grid",
+        scene: "scenes/default.ron",
+        eye: [0.0, 9.0, 14.0],
+        target: [0.0, 0.0, -3.0],
+    },
+    Case {
+        name: "cube_grid_close",
+        scene: "scenes/default.ron",
+        eye: [4.0, 2.0, 5.0],
+        target: [0.0, 0.5, 0.0],
+    },
+    Case {
+        name: "island",
+        scene: "scenes/island.ron",
+        eye: [0.0, 2.0, 10.0],
+        target: [0.0, 1.0, 0.0],
+    },
+    Case {
+        name: "island_from_below",
+        scene: "scenes/island.ron",
+        eye: [6.0, -6.0, 6.0],
+        target: [0.0, 0.0, 0.0],
+    },
+];
+
+#[test]
+fn scenes_match_references() {
+    let update = std::env::var_os("GOLDEN_UPDATE").is_some();
+    let mut failures = Vec::new();
+
+    for case in CASES {
+        let image = render(case);
+        let reference_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.png", case.name));
+
+        if update {
+            image.save(&reference_path).unwrap();
+            continue;
+        }
+        let Ok(reference) = image::open(&reference_path) else {
+            failures.push(format!(
+                "{}: no reference at {:?}, run with GOLDEN_UPDATE=1 to create it",
+                case.name, reference_path
+            ));
+            continue;
+        };
+
+        let comparison = compare(&reference.to_rgba8(), &image);
+        if comparison.differing > MAX_DIFFERING {
+            let dir = output_dir();
+            image.save(dir.join(format!("{}.png", case.name))).unwrap();
+            comparison
+                .diff
+                .save(dir.join(format!("{}.diff.png", case.name)))
+                .unwrap();
+            failures.push(format!(
+                "{}: {:.2}% of pixels differ, up to ΔE {:.1}; see {:?}",
+                case.name,
+                comparison.differing * 100.0,
+                comparison.max_delta_e,
+                dir
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn render(case: &Case) -> RgbaImage {
+    let mut renderer =
+        pollster::block_on(HeadlessRenderer::new(case.scene, WIDTH, HEIGHT)).unwrap();
+    renderer.look_at(case.eye, case.target);
+    renderer.capture().unwrap()
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+struct Comparison {
+    /// Share of pixels past `MAX_DELTA_E`, from 0 to 1.
+    differing: f32,
+    max_delta_e: f32,
+    /// The reference faded to gray, with differing pixels in red.
+    diff: RgbaImage,
+}
+
+fn compare(reference: &RgbaImage, image: &RgbaImage) -> Comparison {
+    if reference.dimensions() != image.dimensions() {
+        return Comparison {
+            differing: 1.0,
+            max_delta_e: f32::INFINITY,
+            diff: image.clone(),
+        };
+    }
+
+    let mut differing = 0;
+    let mut max_delta_e = 0.0f32;
+    let mut diff = RgbaImage::new(image.width(), image.height());
+    for ((expected, actual), out) in reference
+        .pixels()
+        .zip(image.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let delta_e = delta_e(*expected, *actual);
+        max_delta_e = max_delta_e.max(delta_e);
+        *out = if delta_e > MAX_DELTA_E {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let [r, g, b, _] = expected.0;
+            let gray = ((r as u32 + g as u32 + b as u32) / 6 + 64) as u8;
+            Rgba([gray, gray, gray, 255])
+        };
+    }
+
+    Comparison {
+        differing: differing as f32 / (image.width() * image.height()) as f32,
+        max_delta_e,
+        diff,
+    }
+}
+
+/// Distance between two sRGB colors in CIELAB, ignoring alpha.
+fn delta_e(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    let [l1, a1, b1] = lab(a);
+    let [l2, a2, b2] = lab(b);
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+fn lab(color: Rgba<u8>) -> [f32; 3] {
+    let [r, g, b] = [color[0], color[1], color[2]].map(|c| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    // Linear sRGB to XYZ, relative to the D65 white point.
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.089;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
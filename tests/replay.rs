@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+use actual_game::headless::HeadlessRenderer;
+
+const TICK: f32 = 1.0 / 60.0;
+/// Frames are compared this many ticks apart, where both frame rates land.
+const CAPTURE_TICKS: u32 = 4;
+
+/// Walks forward for half a second, turning part way.
+const FLYING: &str = r#"(
+    scene: "scenes/default.ron",
+    events: [
+        (tick: 0, event: Key(code: KeyW, pressed: true)),
+        (tick: 10, event: MouseMotion(delta_x: 40.0, delta_y: -10.0)),
+        (tick: 30, event: Key(code: KeyW, pressed: false)),
+    ],
+)"#;
+
+/// Switches through orbit to following the pillar, then swings the camera
+/// round it, pulling in against the cubes.
+const FOLLOWING: &str = r#"(
+    scene: "scenes/default.ron",
+    events: [
+        (tick: 0, event: Key(code: Tab, pressed: true)),
+        (tick: 1, event: Key(code: Tab, pressed: false)),
+        (tick: 2, event: Key(code: Tab, pressed: true)),
+        (tick: 3, event: Key(code: Tab, pressed: false)),
+        (tick: 6, event: MouseMotion(delta_x: 300.0, delta_y: 0.0)),
+        (tick: 20, event: MouseMotion(delta_x: 300.0, delta_y: 0.0)),
+    ],
+)"#;
+
+/// Walks forward and is still walking when the recording ends.
+const ENDING_HELD: &str = r#"(
+    scene: "scenes/island.ron",
+    events: [
+        (tick: 0, event: Key(code: KeyW, pressed: true)),
+        (tick: 10, event: MouseMotion(delta_x: 10.0, delta_y: 0.0)),
+    ],
+)"#;
+
+/// Plays the recording for a second, `ticks_per_frame` ticks at a time, and
+/// captures a frame every `CAPTURE_TICKS` ticks.
+fn replay(path: &Path, ticks_per_frame: u32) -> Vec<image::RgbaImage> {
+    let mut renderer = pollster::block_on(HeadlessRenderer::replay(path, 160, 120)).unwrap();
+    let mut frames = Vec::new();
+    for frame in 1..=60 / ticks_per_frame {
+        // Whole ticks, so each frame lands exactly on a tick.
+        renderer.advance(ticks_per_frame as f32 * TICK);
+        if (frame * ticks_per_frame).is_multiple_of(CAPTURE_TICKS) {
+            frames.push(renderer.capture().unwrap());
+        }
+    }
+    assert!(!renderer.replaying());
+    frames
+}
+
+/// A recording file of its own, so test runs in parallel don't share one.
+fn recording_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!(
+        "replay-{}-{}.ron",
+        name,
+        std::process::id()
+    ))
+}
+
+/// Plays `recording` smoothly and choppily and checks the frames match.
+fn assert_independent_of_frame_rate(name: &str, recording: &str) {
+    let path = recording_path(name);
+    std::fs::write(&path, recording).unwrap();
+
+    let smooth = replay(&path, 1);
+    let choppy = replay(&path, CAPTURE_TICKS);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(smooth.len(), choppy.len());
+    for (index, (smooth, choppy)) in smooth.iter().zip(&choppy).enumerate() {
+        assert!(
+            smooth == choppy,
+            "{} replays at different frame rates differ {} ticks in",
+            name,
+            (index as u32 + 1) * CAPTURE_TICKS
+        );
+    }
+    // The camera is still moving between these, so matching frames aren't
+    // just the same still picture.
+    assert!(smooth[2] != smooth[5], "{} didn't move the camera", name);
+}
+
+#[test]
+fn replay_is_independent_of_frame_rate() {
+    assert_independent_of_frame_rate("flying", FLYING);
+}
+
+#[test]
+fn follow_camera_replay_is_independent_of_frame_rate() {
+    assert_independent_of_frame_rate("following", FOLLOWING);
+}
+
+#[test]
+fn keys_held_when_a_replay_ends_are_let_go() {
+    let path = recording_path("ending-held");
+    std::fs::write(&path, ENDING_HELD).unwrap();
+    let mut renderer = pollster::block_on(HeadlessRenderer::replay(&path, 160, 120)).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    renderer.advance(5.0 * TICK);
+    let walking = renderer.capture().unwrap();
+    renderer.advance(10.0 * TICK);
+    assert!(!renderer.replaying());
+    let ended = renderer.capture().unwrap();
+    renderer.advance(30.0 * TICK);
+    assert!(walking != ended, "the replay didn't move the camera");
+    assert!(
+        renderer.capture().unwrap() == ended,
+        "the camera kept walking after the replay ended"
+    );
+}